@@ -0,0 +1,181 @@
+use std::thread::sleep;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use log::debug;
+use log::info;
+use log::warn;
+use rppal::gpio::Gpio;
+use rppal::gpio::InputPin;
+use rppal::gpio::Level;
+
+use crate::config::ButtonConfig;
+use crate::config::Config;
+use crate::config::PinPolarity;
+use crate::motor::MoveDirection;
+use crate::movement::Movement;
+use crate::primitives::Centimeter;
+use crate::shutdown::Shutdown;
+
+// How often the buttons are read
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A button connected to an input pin, debounced in software.
+#[derive(Debug)]
+struct Button {
+    pin: InputPin,
+    // The level of the pin while the button is pressed
+    pressed_level: Level,
+    debounce: Duration,
+    // The debounced state
+    pressed: bool,
+    // Since when the pin has differed from the debounced state, if it does
+    changing_since: Option<Instant>,
+    // The direction to jog in while the button is held
+    direction: MoveDirection,
+    // The height to move to on a short press, if any
+    preset: Option<Centimeter>,
+    state: PressState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PressState {
+    Released,
+    // Pressed since the given time, but not handled yet
+    Pressed(Instant),
+    // The press has been handled, waiting for the button to be released
+    Handled,
+}
+
+impl Button {
+    fn new(
+        gpio: &Gpio,
+        config: &ButtonConfig,
+        debounce: Duration,
+        preset: Option<Centimeter>,
+    ) -> Result<Self> {
+        let pin = gpio.get(config.pin)?;
+        let (pin, pressed_level) = match config.polarity {
+            PinPolarity::ActiveHigh => (pin.into_input_pulldown(), Level::High),
+            PinPolarity::ActiveLow => (pin.into_input_pullup(), Level::Low),
+        };
+        Ok(Self {
+            pin,
+            pressed_level,
+            debounce,
+            pressed: false,
+            changing_since: None,
+            direction: config.direction,
+            preset,
+            state: PressState::Released,
+        })
+    }
+
+    /// Reads the pin and returns whether the button is pressed, ignoring
+    /// changes which don't last for the debounce time.
+    fn poll(&mut self) -> bool {
+        let pressed = self.pin.read() == self.pressed_level;
+        if pressed == self.pressed {
+            self.changing_since = None;
+        } else {
+            let changing_since = *self.changing_since.get_or_insert_with(Instant::now);
+            if changing_since.elapsed() >= self.debounce {
+                self.pressed = pressed;
+                self.changing_since = None;
+            }
+        }
+        self.pressed
+    }
+}
+
+/// The buttons controlling the table.
+#[derive(Debug)]
+pub(crate) struct Buttons {
+    buttons: Vec<Button>,
+    // How long a button must be held to jog
+    long_press: Duration,
+}
+
+impl Buttons {
+    /// Claims the configured buttons' pins.
+    ///
+    /// # Errors
+    /// Errors if no buttons are configured, a button's preset is unknown or the
+    /// buttons cannot be initialised.
+    pub(crate) fn new(config: &Config) -> Result<Self> {
+        if config.buttons.buttons.is_empty() {
+            return Err(anyhow!("no buttons configured"));
+        }
+        let gpio = Gpio::new()?;
+        let debounce = Duration::from_millis(config.buttons.debounce_ms);
+        let buttons = config
+            .buttons
+            .buttons
+            .iter()
+            .map(|button_config| {
+                let preset = match &button_config.preset {
+                    Some(name) => Some(
+                        config
+                            .preset(name)
+                            .ok_or_else(|| anyhow!("unknown preset {name:?}"))?,
+                    ),
+                    None => None,
+                };
+                Button::new(&gpio, button_config, debounce, preset)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            buttons,
+            long_press: Duration::from_millis(config.buttons.long_press_ms),
+        })
+    }
+
+    /// Controls the table with the buttons until a shutdown is requested.
+    ///
+    /// A short press moves the table to the button's preset, holding a button
+    /// jogs the table in the button's direction until it is released. Failed
+    /// movements are logged and don't stop the buttons from working.
+    pub(crate) fn run<T: Movement>(
+        &mut self,
+        table: &mut T,
+        shutdown: &Shutdown,
+    ) {
+        info!("Waiting for button presses");
+        while !shutdown.is_requested() {
+            for button in &mut self.buttons {
+                let pressed = button.poll();
+                match (pressed, button.state) {
+                    (true, PressState::Released) => {
+                        button.state = PressState::Pressed(Instant::now());
+                    }
+                    (true, PressState::Pressed(since)) if since.elapsed() >= self.long_press => {
+                        button.state = PressState::Handled;
+                        shutdown.resume();
+                        let direction = button.direction;
+                        info!("Jogging {direction:?}");
+                        if let Err(e) = table.jog(direction, || button.poll()) {
+                            warn!("Jogging {direction:?} failed: {e:#}");
+                        }
+                    }
+                    (false, PressState::Pressed(_)) => {
+                        button.state = PressState::Released;
+                        match button.preset {
+                            Some(height) => {
+                                shutdown.resume();
+                                if let Err(e) = table.move_to_height(height) {
+                                    warn!("Moving to {height:?} failed: {e:#}");
+                                }
+                            }
+                            None => debug!("No preset for the button, ignoring short press"),
+                        }
+                    }
+                    (false, _) => button.state = PressState::Released,
+                    (true, _) => {}
+                }
+            }
+            sleep(POLL_INTERVAL);
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use log::debug;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::motor::MoveDirection;
+use crate::primitives::Centimeter;
+use crate::sensor::SensorCalibrationData;
+use crate::storage::write_atomically;
+
+// The ambient temperature assumed if none is known
+const DEFAULT_TEMPERATURE_CELSIUS: f32 = 20.0;
+// The range the sensor can measure in
+const MIN_SENSOR_DISTANCE_CM: f32 = 2.0;
+const MAX_SENSOR_DISTANCE_CM: f32 = 400.0;
+// How far the distance measured at a calibrated height may deviate from the
+// configured height, accounting for the sensor not being mounted exactly at
+// the table top
+const MAX_MOUNTING_OFFSET_CM: f32 = 20.0;
+// How much the measured travel distance between lowest and highest position
+// may deviate relatively from the configured one
+const MAX_TRAVEL_DEVIATION: f32 = 0.15;
+// The number of consecutive measurements that need to agree for the table to
+// be considered standing still
+const PLATEAU_MEASUREMENTS: usize = 5;
+// How much consecutive measurements may differ while standing still
+const PLATEAU_TOLERANCE_CM: f32 = 0.5;
+// The time the motor gets to start moving the table before an unchanged
+// height is taken as the table already being at its end stop
+const PLATEAU_STARTUP_GRACE: Duration = Duration::from_secs(1);
+
+/// Information about the circumstances under which a calibration was done.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub(crate) struct CalibrationMetadata {
+    // When the calibration was done
+    #[serde(with = "crate::storage::rfc3339")]
+    pub calibrated_at: SystemTime,
+    // The ambient temperature during calibration, the speed of sound and
+    // hence the echo durations depend on it
+    pub temperature_celsius: Option<f32>,
+    // The configured lowest table height at the time of calibration
+    pub min_table_height_cm: Centimeter,
+    // The configured highest table height at the time of calibration
+    pub max_table_height_cm: Centimeter,
+    // The number of sensor samples taken per calibrated height
+    pub sample_count: u8,
+}
+
+/// How the table moves, as measured during calibration.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub(crate) struct MotionCalibration {
+    // The time in seconds it takes to move from the lowest to the highest position
+    pub up_travel_secs: f32,
+    // The time in seconds it takes to move from the highest to the lowest position
+    pub down_travel_secs: f32,
+    // The speed in centimeters per second when moving up
+    pub up_speed_cm_per_sec: f32,
+    // The speed in centimeters per second when moving down
+    pub down_speed_cm_per_sec: f32,
+    // The time in seconds from starting the motor until the table moves
+    pub start_latency_secs: f32,
+    // The time in seconds the table keeps moving after stopping the motor
+    pub stop_latency_secs: f32,
+}
+
+impl MotionCalibration {
+    /// The time it takes to move the table all the way in the given direction.
+    pub(crate) fn travel_time(
+        &self,
+        direction: MoveDirection,
+    ) -> Duration {
+        let travel_secs = match direction {
+            MoveDirection::Up => self.up_travel_secs,
+            MoveDirection::Down => self.down_travel_secs,
+        };
+        Duration::from_secs_f32(travel_secs.max(0.0))
+    }
+
+    /// The time the motor needs to run for moving the table by `distance` in
+    /// the given direction, accounting for the start and stop latencies.
+    pub(crate) fn run_time(
+        &self,
+        direction: MoveDirection,
+        distance: Centimeter,
+    ) -> Duration {
+        let speed_cm_per_sec = match direction {
+            MoveDirection::Up => self.up_speed_cm_per_sec,
+            MoveDirection::Down => self.down_speed_cm_per_sec,
+        };
+        let run_secs = self.start_latency_secs + distance.into_inner() as f32 / speed_cm_per_sec
+            - self.stop_latency_secs;
+        Duration::from_secs_f32(run_secs.max(0.0))
+    }
+}
+
+/// An entry in the calibration history.
+#[derive(Debug)]
+pub(crate) struct CalibrationHistoryEntry {
+    pub id: String,
+    pub data: SensorCalibrationData,
+}
+
+/// Persists calibration data and keeps a history of past calibrations.
+///
+/// The current calibration lives in the configured calibration file, every
+/// saved calibration is additionally kept in a history directory next to it,
+/// e.g. `calibration.history/` for `calibration.toml`.
+#[derive(Debug)]
+pub(crate) struct CalibrationStore {
+    calibration_file: PathBuf,
+    history_dir: PathBuf,
+}
+
+impl CalibrationStore {
+    /// Creates a new store for the given calibration file.
+    pub(crate) fn new<P: AsRef<Path>>(calibration_file: P) -> Self {
+        let calibration_file = calibration_file.as_ref().to_path_buf();
+        let mut history_dir_name = calibration_file
+            .file_stem()
+            .unwrap_or_else(|| "calibration".as_ref())
+            .to_os_string();
+        history_dir_name.push(".history");
+        let history_dir = calibration_file.with_file_name(history_dir_name);
+        Self {
+            calibration_file,
+            history_dir,
+        }
+    }
+
+    /// The path of the current calibration file.
+    pub(crate) fn calibration_file(&self) -> &Path {
+        &self.calibration_file
+    }
+
+    /// Loads the current calibration data, if any.
+    pub(crate) fn current(&self) -> Result<Option<SensorCalibrationData>> {
+        SensorCalibrationData::load(&self.calibration_file)
+    }
+
+    /// Atomically saves `data` as the current calibration and adds it to the
+    /// history.
+    pub(crate) fn save(
+        &self,
+        data: &SensorCalibrationData,
+    ) -> Result<()> {
+        let raw_data = toml::to_string(data)?;
+        let calibrated_at = data
+            .metadata
+            .as_ref()
+            .map(|metadata| metadata.calibrated_at)
+            .unwrap_or_else(SystemTime::now);
+        let history_file = self.new_history_file(calibrated_at);
+        write_atomically(&history_file, raw_data.as_bytes())?;
+        debug!("Calibration data added to history at {history_file:?}");
+        write_atomically(&self.calibration_file, raw_data.as_bytes())?;
+        debug!("Calibration data written to {:?}", self.calibration_file);
+        Ok(())
+    }
+
+    /// Lists all calibrations in the history, oldest first.
+    pub(crate) fn list(&self) -> Result<Vec<CalibrationHistoryEntry>> {
+        let dir_entries = match fs::read_dir(&self.history_dir) {
+            Ok(dir_entries) => dir_entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut ids = vec![];
+        for dir_entry in dir_entries {
+            let path = dir_entry?.path();
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                if let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    ids.push(id.to_string());
+                }
+            }
+        }
+        // The ids are timestamps which sort chronologically
+        ids.sort();
+        ids.into_iter()
+            .map(|id| {
+                let data = self.get(&id)?;
+                Ok(CalibrationHistoryEntry { id, data })
+            })
+            .collect()
+    }
+
+    /// Loads the calibration with the given id from the history.
+    pub(crate) fn get(
+        &self,
+        id: &str,
+    ) -> Result<SensorCalibrationData> {
+        SensorCalibrationData::load(self.history_file(id)?)?
+            .ok_or_else(|| anyhow!("no calibration with id {id} in the history"))
+    }
+
+    /// Makes the calibration with the given id from the history the current
+    /// one.
+    pub(crate) fn rollback(
+        &self,
+        id: &str,
+    ) -> Result<SensorCalibrationData> {
+        let data = self.get(id)?;
+        let raw_data = toml::to_string(&data)?;
+        write_atomically(&self.calibration_file, raw_data.as_bytes())?;
+        debug!(
+            "Rolled back calibration data in {:?} to {id}",
+            self.calibration_file
+        );
+        Ok(data)
+    }
+
+    /// The path of the history entry with the given id.
+    ///
+    /// # Errors
+    /// Errors if the id could point outside of the history directory.
+    fn history_file(
+        &self,
+        id: &str,
+    ) -> Result<PathBuf> {
+        if id.is_empty() || id.contains(['/', '\\']) || id.contains("..") {
+            return Err(anyhow!("invalid calibration id {id:?}"));
+        }
+        Ok(self.history_dir.join(format!("{id}.toml")))
+    }
+
+    /// A path for a new history entry of a calibration done at `time` which
+    /// does not overwrite an existing entry.
+    fn new_history_file(
+        &self,
+        time: SystemTime,
+    ) -> PathBuf {
+        let id = history_id(time);
+        let mut path = self.history_dir.join(format!("{id}.toml"));
+        let mut suffix = 1;
+        while path.exists() {
+            path = self.history_dir.join(format!("{id}-{suffix}.toml"));
+            suffix += 1;
+        }
+        path
+    }
+}
+
+/// Converts the duration of an ultrasonic echo into the distance to the object
+/// in centimeters.
+///
+/// The speed of sound depends on the temperature, a room temperature of 20°C
+/// is assumed if it is unknown.
+pub(crate) fn echo_secs_to_cm(
+    echo_secs: f32,
+    temperature_celsius: Option<f32>,
+) -> f32 {
+    let temperature_celsius = temperature_celsius.unwrap_or(DEFAULT_TEMPERATURE_CELSIUS);
+    let speed_of_sound_cm_per_sec = (331.3 + 0.606 * temperature_celsius) * 100.0;
+    // The echo travels the distance twice
+    echo_secs * speed_of_sound_cm_per_sec / 2.0
+}
+
+/// Checks that calibration data is plausible before it is accepted.
+///
+/// # Errors
+/// Errors with an explanation if the echo durations do not increase with the
+/// height, are out of the sensor's range or do not match the configured
+/// heights.
+pub(crate) fn validate(data: &SensorCalibrationData) -> Result<()> {
+    let temperature_celsius = data
+        .metadata
+        .as_ref()
+        .and_then(|metadata| metadata.temperature_celsius);
+    if data.min_height >= data.max_height {
+        return Err(anyhow!(
+            "the lowest height {:?} must be lower than the highest height {:?}",
+            data.min_height,
+            data.max_height
+        ));
+    }
+    if data.min_height_echo_secs >= data.max_height_echo_secs {
+        return Err(anyhow!(
+            "the echo at the lowest position ({}s) is not shorter than at the highest position \
+             ({}s), did the table move and is the sensor facing the floor?",
+            data.min_height_echo_secs,
+            data.max_height_echo_secs
+        ));
+    }
+    for (height, echo_secs) in [
+        (data.min_height, data.min_height_echo_secs),
+        (data.max_height, data.max_height_echo_secs),
+    ] {
+        let distance_cm = echo_secs_to_cm(echo_secs, temperature_celsius);
+        if !(MIN_SENSOR_DISTANCE_CM..=MAX_SENSOR_DISTANCE_CM).contains(&distance_cm) {
+            return Err(anyhow!(
+                "the measured distance of {distance_cm:.1}cm at height {height:?} is out of the \
+                 sensor's range"
+            ));
+        }
+        let offset_cm = (distance_cm - height.into_inner() as f32).abs();
+        if offset_cm > MAX_MOUNTING_OFFSET_CM {
+            return Err(anyhow!(
+                "the measured distance of {distance_cm:.1}cm deviates by {offset_cm:.1}cm from \
+                 the configured height {height:?}, check the configured table heights"
+            ));
+        }
+    }
+    let measured_travel_cm = echo_secs_to_cm(
+        data.max_height_echo_secs - data.min_height_echo_secs,
+        temperature_celsius,
+    );
+    let configured_travel_cm = (data.max_height - data.min_height).into_inner() as f32;
+    let travel_deviation = (measured_travel_cm - configured_travel_cm).abs() / configured_travel_cm;
+    if travel_deviation > MAX_TRAVEL_DEVIATION {
+        return Err(anyhow!(
+            "the table moved {measured_travel_cm:.1}cm according to the sensor but \
+             {configured_travel_cm}cm according to the configuration, did the table reach its \
+             lowest and highest positions?"
+        ));
+    }
+    if let Some(motion) = &data.motion {
+        for speed_cm_per_sec in [motion.up_speed_cm_per_sec, motion.down_speed_cm_per_sec] {
+            if !speed_cm_per_sec.is_finite() || speed_cm_per_sec <= 0.0 {
+                return Err(anyhow!(
+                    "implausible table speed of {speed_cm_per_sec}cm/s measured"
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Detects when the table stops moving, e.g. because it reached an end stop,
+/// from consecutive echo measurements.
+#[derive(Debug, Default)]
+pub(crate) struct PlateauDetector {
+    first_measurement: Option<(Instant, f32)>,
+    recent_measurements: VecDeque<(Instant, f32)>,
+    movement_start: Option<Instant>,
+}
+
+impl PlateauDetector {
+    /// Adds a new echo measurement and returns whether the table stands still.
+    pub(crate) fn push(
+        &mut self,
+        measured_at: Instant,
+        echo_duration: Duration,
+    ) -> bool {
+        let distance_cm = echo_secs_to_cm(echo_duration.as_secs_f32(), None);
+        let (_, first_distance_cm) = *self
+            .first_measurement
+            .get_or_insert((measured_at, distance_cm));
+        if self.movement_start.is_none()
+            && (distance_cm - first_distance_cm).abs() > PLATEAU_TOLERANCE_CM
+        {
+            self.movement_start = Some(measured_at);
+        }
+        if self.recent_measurements.len() == PLATEAU_MEASUREMENTS {
+            self.recent_measurements.pop_front();
+        }
+        self.recent_measurements
+            .push_back((measured_at, distance_cm));
+        self.plateau_start().is_some()
+    }
+
+    /// The time at which the table was first seen moving, if it did.
+    pub(crate) fn movement_start(&self) -> Option<Instant> {
+        self.movement_start
+    }
+
+    /// The time from which on the table has been standing still, if it does.
+    pub(crate) fn plateau_start(&self) -> Option<Instant> {
+        let (first_measured_at, _) = self.first_measurement?;
+        let (window_start, _) = *self.recent_measurements.front()?;
+        if self.recent_measurements.len() < PLATEAU_MEASUREMENTS {
+            return None;
+        }
+        let (min_cm, max_cm) = self.recent_measurements.iter().fold(
+            (f32::MAX, f32::MIN),
+            |(min_cm, max_cm), (_, distance_cm)| {
+                (min_cm.min(*distance_cm), max_cm.max(*distance_cm))
+            },
+        );
+        if max_cm - min_cm > PLATEAU_TOLERANCE_CM {
+            return None;
+        }
+        if self.movement_start.is_some() {
+            Some(window_start)
+        } else if window_start.duration_since(first_measured_at) >= PLATEAU_STARTUP_GRACE {
+            // The table has not moved at all, it was at the end stop already
+            Some(first_measured_at)
+        } else {
+            None
+        }
+    }
+}
+
+/// Turns a timestamp into a history id like `20240101T120000.123456789Z`.
+/// Calibrations saved within the same second get distinct ids.
+fn history_id(time: SystemTime) -> String {
+    humantime::format_rfc3339_nanos(time)
+        .to_string()
+        .replace(['-', ':'], "")
+}
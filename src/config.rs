@@ -1,10 +1,17 @@
+use std::collections::BTreeMap;
+use std::env;
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 
+use anyhow::anyhow;
 use anyhow::Result;
+use clap::ValueEnum;
+use log::LevelFilter;
 use serde::Deserialize;
 
+use crate::motor::MoveDirection;
+use crate::presets;
 use crate::primitives::Centimeter;
 
 /// Configuration data for the whole motorized standing desk.
@@ -13,10 +20,41 @@ pub(crate) struct Config {
     pub table: TableConfig,
     pub sensor: SensorConfig,
     pub motor: MotorConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    // No standing goal is tracked if unset
+    #[serde(default)]
+    pub goal: Option<GoalConfig>,
+    // No metrics are exported if unset
+    #[serde(default)]
+    pub metrics: Option<MetricsConfig>,
+    #[serde(default)]
+    pub control: ControlConfig,
+    // Named heights in addition to the sitting and standing heights. Presets
+    // saved at runtime are added when loading the config.
+    #[serde(default)]
+    pub presets: BTreeMap<String, Centimeter>,
+    // The file presets saved at runtime are stored in, which take precedence
+    // over configured presets with the same name. Relative paths are resolved
+    // against the directory of the config file.
+    #[serde(default = "default_presets_file")]
+    pub presets_file: PathBuf,
+    #[serde(default)]
+    pub buttons: ButtonsConfig,
+    #[serde(default)]
+    pub presence: PresenceConfig,
+    #[serde(default)]
+    pub warning: WarningConfig,
+    #[serde(default)]
+    pub feedback: FeedbackConfig,
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
 }
 
 /// Configuration data for the standing desk.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone, Copy)]
 pub(crate) struct TableConfig {
     pub max_table_height_cm: Centimeter,
     pub min_table_height_cm: Centimeter,
@@ -27,30 +65,639 @@ pub(crate) struct TableConfig {
 /// Configuration data for the distance sensor.
 #[derive(Debug, Deserialize)]
 pub(crate) struct SensorConfig {
-    // The pin number controlling the distance sensor's trigger
-    pub trigger_pin: u8,
-    // The pin number listening for the distance sensor's echo signal
-    pub echo_pin: u8,
-    // The calibration file for the sensor
+    // The kind of sensor measuring the table height
+    #[serde(default)]
+    pub kind: SensorKind,
+    // The pin number controlling the distance sensor's trigger, required for the
+    // HCSR04 sensor
+    pub trigger_pin: Option<u8>,
+    // The pin number listening for the distance sensor's echo signal, required for
+    // the HCSR04 sensor
+    pub echo_pin: Option<u8>,
+    // The calibration file for the sensor. Relative paths are resolved against
+    // the directory of the config file.
+    #[serde(default = "default_calibration_file")]
     pub calibration_file: PathBuf,
+    // The time in seconds the table takes to move all the way up, used for
+    // estimating the height without a sensor if no calibration data is available
+    pub up_travel_secs: Option<f32>,
+    // The time in seconds the table takes to move all the way down, used for
+    // estimating the height without a sensor if no calibration data is available
+    pub down_travel_secs: Option<f32>,
+    // The file the table's last known position is persisted in when no sensor is
+    // used. Relative paths are resolved against the directory of the config file.
+    #[serde(default = "default_position_file")]
+    pub position_file: PathBuf,
+}
+
+/// The kinds of sensors for determining the table height.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SensorKind {
+    /// The HCSR04 ultrasonic distance sensor.
+    #[default]
+    Hcsr04,
+    /// No sensor, the height is estimated from the table's movements.
+    Sensorless,
 }
 
 /// Configuration data for the standing desk motor.
-#[derive(Debug, Deserialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Clone)]
 pub(crate) struct MotorConfig {
     // The pin number controlling the motor's upwards movement
     pub up_pin: u8,
     // The pin number controlling the motor's downwards movement
     pub down_pin: u8,
+    // The level of the up pin which makes the motor move, many relay boards
+    // are active-low
+    #[serde(default)]
+    pub up_pin_polarity: PinPolarity,
+    // The state the up pin is left in when it is released
+    #[serde(default)]
+    pub up_pin_release: PinRelease,
+    // The level of the down pin which makes the motor move
+    #[serde(default)]
+    pub down_pin_polarity: PinPolarity,
+    // The state the down pin is left in when it is released
+    #[serde(default)]
+    pub down_pin_release: PinRelease,
     // The maximimum time in seconds the motor should be allowed to run at a time
     pub timeout_secs: u64,
+    // The pause in milliseconds between stopping and moving in the opposite
+    // direction, sparing the relays and the motor
+    #[serde(default = "default_dead_time_ms")]
+    pub dead_time_ms: u64,
+    // The shortest time in milliseconds the motor runs once started, so that
+    // the relays are not switched on and off in quick succession
+    #[serde(default)]
+    pub min_pulse_ms: u64,
+    // Ramps the motor speed up and down with PWM. Only suitable for H-bridge
+    // motor controllers, leave unset for relays.
+    #[serde(default)]
+    pub ramp: Option<RampConfig>,
+    // Toggles a pin while the motor runs so that an external watchdog can cut
+    // the motor's power if the program hangs
+    #[serde(default)]
+    pub heartbeat: Option<HeartbeatConfig>,
+    // Limits how long the motor may run in total to keep it from overheating,
+    // the motor's run time is not limited if unset
+    #[serde(default)]
+    pub duty_cycle: Option<DutyCycleConfig>,
+}
+
+/// The level of a pin which makes the motor move.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PinPolarity {
+    /// The motor moves while the pin is high.
+    #[default]
+    ActiveHigh,
+    /// The motor moves while the pin is low.
+    ActiveLow,
+}
+
+/// The state the motor pins are left in when they are released.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PinRelease {
+    /// Keep driving the pins at their idle level, which keeps active-low
+    /// relay boards from switching on once the pins float.
+    #[default]
+    Idle,
+    /// Reset the pins to their previous mode, usually inputs. Only suitable
+    /// for active-high pins.
+    Reset,
+}
+
+/// Configuration data for ramping the motor speed up and down.
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub(crate) struct RampConfig {
+    // The time in milliseconds to ramp up to full speed when starting
+    pub ramp_up_ms: u64,
+    // The time in milliseconds to ramp down to a halt when stopping
+    pub ramp_down_ms: u64,
+    // The frequency of the PWM signal
+    #[serde(default = "default_pwm_frequency_hz")]
+    pub pwm_frequency_hz: f64,
+}
+
+/// Configuration data for a heartbeat signal to an external watchdog.
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub(crate) struct HeartbeatConfig {
+    // The pin number to toggle
+    pub pin: u8,
+    // The time in milliseconds between toggling the pin
+    #[serde(default = "default_heartbeat_interval_ms")]
+    pub interval_ms: u64,
+}
+
+/// Configuration data for protecting the motor from overheating.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(crate) struct DutyCycleConfig {
+    // The share of time the motor may run, e.g. 0.1 for 10% as commonly
+    // specified for desk motors. Set to 1 to disable the protection.
+    pub max_duty_cycle: f32,
+    // The length in minutes of the sliding window the duty cycle applies to
+    pub window_mins: u64,
+    // What to do with a movement while the motor is cooling down
+    pub policy: DutyCyclePolicy,
+    // The file the recent motor runs are persisted in. Relative paths are
+    // resolved against the directory of the config file.
+    pub state_file: PathBuf,
+}
+
+/// What to do with a movement while the motor is cooling down.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DutyCyclePolicy {
+    /// Refuse to move, reporting the remaining cooldown.
+    #[default]
+    Refuse,
+    /// Wait for the motor to cool down, then move.
+    Wait,
+}
+
+/// Configuration data for recording the table's movements and heights.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(crate) struct TelemetryConfig {
+    // Whether telemetry is recorded at all
+    pub enabled: bool,
+    // The append-only log the telemetry is recorded in. Relative paths are
+    // resolved against the directory of the config file.
+    pub log_file: PathBuf,
+    // The longest time in minutes between two records for which the table is
+    // assumed to have stayed at the same height, longer gaps are not counted
+    // as sitting or standing time
+    pub max_gap_mins: u64,
+}
+
+/// Configuration data for the daily standing goal.
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub(crate) struct GoalConfig {
+    // The time in minutes to spend standing per day
+    pub daily_standing_mins: u64,
+    // The local hour the workday starts at, standing time is expected to
+    // accumulate evenly over the workday
+    #[serde(default = "default_workday_start_hour")]
+    pub workday_start_hour: u32,
+    // The local hour the workday ends at
+    #[serde(default = "default_workday_end_hour")]
+    pub workday_end_hour: u32,
+}
+
+/// Configuration data for exporting metrics.
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct MetricsConfig {
+    // The address to serve Prometheus metrics on, e.g. `0.0.0.0:9184`
+    pub listen_address: String,
+}
+
+/// Configuration data for physical buttons controlling the desk.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(crate) struct ButtonsConfig {
+    // The time in milliseconds a button's state must be stable to count
+    pub debounce_ms: u64,
+    // The time in milliseconds a button must be held to jog instead of
+    // recalling its preset
+    pub long_press_ms: u64,
+    #[serde(rename = "button")]
+    pub buttons: Vec<ButtonConfig>,
+}
+
+/// Configuration data for a single button.
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct ButtonConfig {
+    // The pin number the button is connected to
+    pub pin: u8,
+    // The level of the pin while the button is pressed. Active-low buttons
+    // connect the pin to ground and use the internal pull-up resistor.
+    #[serde(default = "default_button_polarity")]
+    pub polarity: PinPolarity,
+    // The direction to jog in while the button is held
+    pub direction: MoveDirection,
+    // The preset to move to on a short press, e.g. `standing`
+    pub preset: Option<String>,
+}
+
+/// Configuration data for detecting whether somebody is at the desk before
+/// automated movements.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(crate) struct PresenceConfig {
+    // Whether to assume somebody is present if a source fails
+    pub present_on_error: bool,
+    // Somebody is present if any of the sources detects them. Without sources,
+    // somebody is always assumed to be present.
+    #[serde(rename = "source")]
+    pub sources: Vec<PresenceSourceConfig>,
+}
+
+/// A source for detecting whether somebody is at the desk.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub(crate) enum PresenceSourceConfig {
+    /// A passive infrared motion sensor.
+    Pir {
+        // The pin number the sensor's output is connected to
+        pin: u8,
+        // The time in seconds to watch for motion
+        #[serde(default = "default_pir_sample_secs")]
+        sample_secs: u64,
+    },
+    /// An HCSR04 ultrasonic distance sensor pointed at the chair.
+    Ultrasonic {
+        trigger_pin: u8,
+        echo_pin: u8,
+        // The chair is occupied if something is closer than this
+        max_distance_cm: f32,
+    },
+    /// A file touched on activity.
+    File {
+        path: PathBuf,
+        // The file must have been modified within this many minutes
+        max_age_mins: u64,
+    },
+    /// A shell command exiting successfully if somebody is present.
+    Command { command: String },
+}
+
+/// Configuration data for warning before automated movements.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(crate) struct WarningConfig {
+    // The time in seconds between the warning and the movement, during which
+    // the movement can be cancelled. No warning is given if zero.
+    pub delay_secs: u64,
+    // A shell command run at the start of the warning period, e.g. to show a
+    // desktop notification
+    pub notify_command: Option<String>,
+    // The file recording cancelled and snoozed movements. Relative paths are
+    // resolved against the directory of the config file.
+    pub snooze_file: PathBuf,
+}
+
+/// Configuration data for the buzzer and status LED.
+#[derive(Debug, Default, Deserialize, Clone, Copy)]
+pub(crate) struct FeedbackConfig {
+    // The pin number of an active buzzer
+    pub buzzer_pin: Option<u8>,
+    // The pin number of an LED showing whether the table is moving or a
+    // movement failed
+    pub led_pin: Option<u8>,
+}
+
+/// Configuration data for posting events to webhooks.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(crate) struct WebhooksConfig {
+    // The plain HTTP URLs to post the events to
+    pub urls: Vec<String>,
+    // The time in seconds to wait for a webhook to be delivered
+    pub timeout_secs: u64,
+    // How often to try delivering a webhook before keeping it queued for later
+    pub max_attempts: u32,
+    // The time to wait before the first retry, doubled with every retry
+    pub retry_delay_ms: u64,
+    // The file webhooks are queued in until they are delivered. Relative paths
+    // are resolved against the directory of the config file.
+    pub queue_file: PathBuf,
+}
+
+/// Configuration data for logging.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(crate) struct LoggingConfig {
+    // The level to log at unless overridden with `-d`
+    pub level: LevelFilter,
+    // Levels for specific modules by module path, e.g. `standup::motor`
+    pub modules: BTreeMap<String, LevelFilter>,
+    pub format: LogFormat,
+    // A file to write the logs to in addition to stderr. Relative paths are
+    // resolved against the directory of the config file.
+    pub file: Option<PathBuf>,
+    // The log file is rotated once it exceeds this size
+    pub max_file_size_mb: u64,
+    // The number of rotated log files to keep
+    pub max_files: u32,
+    // Whether to also send the logs to the local syslog daemon
+    pub syslog: bool,
+}
+
+/// The format of log lines written to stderr and the log file.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Configuration data for controlling the desk from other processes.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(crate) struct ControlConfig {
+    // The file containing the id of the process controlling the desk, used
+    // for stopping it. Relative paths are resolved against the directory of
+    // the config file.
+    pub pid_file: PathBuf,
+    // The file locked while controlling the desk so that only one process at a
+    // time drives the motor. Relative paths are resolved against the directory
+    // of the config file.
+    pub lock_file: PathBuf,
+    // What to do if another process already controls the desk
+    pub on_conflict: ConflictPolicy,
+    // The socket `standup serve` accepts commands on, unless systemd passes
+    // one. Relative paths are resolved against the directory of the config
+    // file.
+    pub socket_path: PathBuf,
+}
+
+/// What to do if another process already controls the desk.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ConflictPolicy {
+    /// Exit with an error right away.
+    #[default]
+    Fail,
+    /// Wait for the other process to finish.
+    Wait,
+    /// Stop the other process and take over.
+    Preempt,
+}
+
+impl Default for ButtonsConfig {
+    fn default() -> Self {
+        Self {
+            debounce_ms: 30,
+            long_press_ms: 500,
+            buttons: vec![],
+        }
+    }
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        Self {
+            present_on_error: true,
+            sources: vec![],
+        }
+    }
+}
+
+impl Default for WarningConfig {
+    fn default() -> Self {
+        Self {
+            delay_secs: 0,
+            notify_command: None,
+            snooze_file: state_dir().join("snooze.toml"),
+        }
+    }
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            urls: vec![],
+            timeout_secs: 5,
+            max_attempts: 3,
+            retry_delay_ms: 1000,
+            queue_file: state_dir().join("webhooks.jsonl"),
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Error,
+            modules: BTreeMap::new(),
+            format: LogFormat::default(),
+            file: None,
+            max_file_size_mb: 10,
+            max_files: 5,
+            syslog: false,
+        }
+    }
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self {
+            pid_file: state_dir().join("standup.pid"),
+            lock_file: state_dir().join("standup.lock"),
+            on_conflict: ConflictPolicy::default(),
+            socket_path: state_dir().join("standup.sock"),
+        }
+    }
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            log_file: state_dir().join("telemetry.jsonl"),
+            max_gap_mins: 60,
+        }
+    }
+}
+
+impl Default for DutyCycleConfig {
+    fn default() -> Self {
+        Self {
+            max_duty_cycle: 0.1,
+            window_mins: 20,
+            policy: DutyCyclePolicy::default(),
+            state_file: state_dir().join("duty_cycle.toml"),
+        }
+    }
+}
+
+impl TableConfig {
+    /// The height from which on the table counts as being in the standing
+    /// position, halfway between the sitting and standing heights.
+    pub(crate) fn standing_threshold(&self) -> Centimeter {
+        let threshold = (self.sitting_height_cm.into_inner() as u16
+            + self.standing_height_cm.into_inner() as u16)
+            / 2;
+        Centimeter(threshold as u8)
+    }
 }
 
 impl Config {
+    /// All presets by name, including `sitting` and `standing`, ordered by
+    /// height.
+    pub(crate) fn all_presets(&self) -> Vec<(String, Centimeter)> {
+        let mut presets: Vec<(String, Centimeter)> = [
+            ("sitting".to_string(), self.table.sitting_height_cm),
+            ("standing".to_string(), self.table.standing_height_cm),
+        ]
+        .into_iter()
+        .chain(
+            self.presets
+                .iter()
+                .filter(|(name, _)| !matches!(name.as_str(), "sitting" | "standing"))
+                .map(|(name, height)| (name.clone(), *height)),
+        )
+        .collect();
+        presets.sort_by_key(|(_, height)| *height);
+        presets
+    }
+
+    /// The height of a preset, which is either configured in the presets or
+    /// one of `sitting` and `standing`.
+    pub(crate) fn preset(
+        &self,
+        name: &str,
+    ) -> Option<Centimeter> {
+        match name {
+            "sitting" => Some(self.table.sitting_height_cm),
+            "standing" => Some(self.table.standing_height_cm),
+            _ => self.presets.get(name).copied(),
+        }
+    }
+
     /// Loads a configuration from a file.
+    ///
+    /// Relative paths in the configuration are resolved against the directory
+    /// containing the configuration file so that the program behaves the same
+    /// regardless of the working directory it is started from.
     pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<Config> {
+        let path = path.as_ref();
         let raw_config = fs::read_to_string(path)?;
-        let config = toml::from_str(&raw_config)?;
+        let mut config: Config = toml::from_str(&raw_config)?;
+        let config_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        config.sensor.calibration_file = resolve_path(config_dir, &config.sensor.calibration_file);
+        config.sensor.position_file = resolve_path(config_dir, &config.sensor.position_file);
+        config.telemetry.log_file = resolve_path(config_dir, &config.telemetry.log_file);
+        if let Some(duty_cycle) = &mut config.motor.duty_cycle {
+            duty_cycle.state_file = resolve_path(config_dir, &duty_cycle.state_file);
+        }
+        config.control.pid_file = resolve_path(config_dir, &config.control.pid_file);
+        config.control.lock_file = resolve_path(config_dir, &config.control.lock_file);
+        config.control.socket_path = resolve_path(config_dir, &config.control.socket_path);
+        config.warning.snooze_file = resolve_path(config_dir, &config.warning.snooze_file);
+        config.webhooks.queue_file = resolve_path(config_dir, &config.webhooks.queue_file);
+        config.logging.file = config
+            .logging
+            .file
+            .map(|file| resolve_path(config_dir, &file));
+        config.presets_file = resolve_path(config_dir, &config.presets_file);
+        config.presets.extend(presets::load(&config.presets_file)?);
+        config.validate()?;
         Ok(config)
     }
+
+    /// Checks the configuration for conflicts which cannot be caught when
+    /// parsing it.
+    fn validate(&self) -> Result<()> {
+        let motor_pins = [
+            ("up", self.motor.up_pin_polarity, self.motor.up_pin_release),
+            (
+                "down",
+                self.motor.down_pin_polarity,
+                self.motor.down_pin_release,
+            ),
+        ];
+        for (name, polarity, release) in motor_pins {
+            if polarity == PinPolarity::ActiveLow && release == PinRelease::Reset {
+                return Err(anyhow!(
+                    "the {name} pin is active-low and must not be reset on release, a floating \
+                     pin can switch on the relay"
+                ));
+            }
+        }
+        let sensor_pins = match self.sensor.kind {
+            SensorKind::Hcsr04 => [self.sensor.trigger_pin, self.sensor.echo_pin],
+            SensorKind::Sensorless => [None, None],
+        };
+        for source in &self.presence.sources {
+            if let PresenceSourceConfig::Ultrasonic {
+                trigger_pin,
+                echo_pin,
+                ..
+            } = source
+            {
+                if sensor_pins.contains(&Some(*trigger_pin))
+                    || sensor_pins.contains(&Some(*echo_pin))
+                {
+                    return Err(anyhow!(
+                        "the ultrasonic presence sensor must not share pins with the table's \
+                         distance sensor"
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Resolves `path` against `base_dir` if it is relative.
+fn resolve_path(
+    base_dir: &Path,
+    path: &Path,
+) -> PathBuf {
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        base_dir.join(path)
+    }
+}
+
+/// The directory for persisting state between runs, following the XDG base
+/// directory specification.
+///
+/// Falls back to `$HOME/.local/state/standup` and finally to the working
+/// directory if neither `XDG_STATE_HOME` nor `HOME` are set.
+pub(crate) fn state_dir() -> PathBuf {
+    env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+        .map(|dir| dir.join("standup"))
+        .unwrap_or_default()
+}
+
+fn default_calibration_file() -> PathBuf {
+    state_dir().join("calibration.toml")
+}
+
+fn default_presets_file() -> PathBuf {
+    state_dir().join("presets.toml")
+}
+
+fn default_position_file() -> PathBuf {
+    state_dir().join("position.toml")
+}
+
+fn default_dead_time_ms() -> u64 {
+    500
+}
+
+fn default_button_polarity() -> PinPolarity {
+    PinPolarity::ActiveLow
+}
+
+fn default_pir_sample_secs() -> u64 {
+    5
+}
+
+fn default_heartbeat_interval_ms() -> u64 {
+    100
+}
+
+fn default_pwm_frequency_hz() -> f64 {
+    1000.0
+}
+
+fn default_workday_start_hour() -> u32 {
+    9
+}
+
+fn default_workday_end_hour() -> u32 {
+    17
 }
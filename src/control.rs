@@ -0,0 +1,177 @@
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::thread::sleep;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use log::debug;
+use log::info;
+use log::warn;
+
+use crate::config::ConflictPolicy;
+use crate::config::ControlConfig;
+use crate::shutdown::Shutdown;
+use crate::storage::write_atomically;
+
+// How often to retry acquiring the instance lock while waiting for it
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+// How long to wait for the other process to release the lock after stopping
+// its movement, long-running commands keep it
+const PREEMPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An exclusive lock held while controlling the desk, so that only one process
+/// at a time drives the motor. The lock is released on drop.
+#[derive(Debug)]
+pub(crate) struct InstanceLock {
+    // The lock is tied to the open file
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Acquires the lock, handling another process holding it according to
+    /// `policy`.
+    ///
+    /// # Errors
+    /// Errors if the lock is held by another process and `policy` is to fail,
+    /// if a shutdown is requested while waiting for the lock or if the lock
+    /// file cannot be opened.
+    pub(crate) fn acquire(
+        config: &ControlConfig,
+        policy: ConflictPolicy,
+        shutdown: &Shutdown,
+    ) -> Result<Self> {
+        if let Some(dir) = config.lock_file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&config.lock_file)?;
+        if try_lock(&file)? {
+            return Ok(Self { _file: file });
+        }
+        match policy {
+            ConflictPolicy::Fail => {
+                return Err(anyhow!(
+                    "the desk is already being controlled by another process, run `standup \
+                     stop` to stop its movement"
+                ))
+            }
+            ConflictPolicy::Wait => {
+                info!("Waiting for another process to finish controlling the desk")
+            }
+            ConflictPolicy::Preempt => {
+                info!("Stopping the movement of the other process controlling the desk");
+                stop(config)?;
+            }
+        }
+        let start = Instant::now();
+        while !try_lock(&file)? {
+            if shutdown.is_requested() {
+                return Err(anyhow!("interrupted while waiting for the desk to be free"));
+            }
+            if policy == ConflictPolicy::Preempt && start.elapsed() >= PREEMPT_TIMEOUT {
+                return Err(anyhow!(
+                    "the other process keeps controlling the desk after stopping its movement, \
+                     e.g. `standup buttons` or `standup tui`"
+                ));
+            }
+            sleep(LOCK_RETRY_INTERVAL);
+        }
+        Ok(Self { _file: file })
+    }
+}
+
+/// Tries to lock `file` exclusively, returning whether it has been locked.
+fn try_lock(file: &File) -> Result<bool> {
+    // SAFETY: `flock` has no memory safety requirements and the file
+    // descriptor is valid while `file` is borrowed
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let error = std::io::Error::last_os_error();
+    match error.kind() {
+        ErrorKind::WouldBlock => Ok(false),
+        _ => Err(error.into()),
+    }
+}
+
+/// A file containing the id of the process controlling the desk, so that
+/// other processes can stop it. The file is removed on drop.
+#[derive(Debug)]
+pub(crate) struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Writes the id of the current process to `path`.
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        write_atomically(path, process::id().to_string().as_bytes())?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Another process may have replaced the file in the meantime
+        if read_pid(&self.path).ok().flatten() == Some(process::id()) {
+            if let Err(e) = fs::remove_file(&self.path) {
+                warn!("Could not remove the pid file {:?}: {e}", self.path);
+            }
+        }
+    }
+}
+
+/// Stops the current movement of the process controlling the desk, if any, by
+/// sending it SIGUSR1. The process keeps running, a command running only for
+/// the movement ends with it.
+///
+/// Returns the id of the process.
+pub(crate) fn stop(config: &ControlConfig) -> Result<Option<u32>> {
+    let Some(pid) = read_pid(&config.pid_file)? else {
+        return Ok(None);
+    };
+    if !is_standup_process(pid) {
+        debug!("Removing stale pid file {:?}", config.pid_file);
+        fs::remove_file(&config.pid_file)?;
+        return Ok(None);
+    }
+    let pid_t = libc::pid_t::try_from(pid)?;
+    // SAFETY: `kill` has no memory safety requirements
+    if unsafe { libc::kill(pid_t, libc::SIGUSR1) } != 0 {
+        return Err(anyhow!(
+            "could not stop the movement of process {pid}: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(Some(pid))
+}
+
+fn read_pid(path: &Path) -> Result<Option<u32>> {
+    match fs::read_to_string(path) {
+        Ok(raw_pid) => Ok(Some(raw_pid.trim().parse()?)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether the process with the given id is running this program, which guards
+/// against signalling an unrelated process reusing the id of a stale pid file.
+fn is_standup_process(pid: u32) -> bool {
+    let Ok(exe) = fs::read_link(format!("/proc/{pid}/exe")) else {
+        return false;
+    };
+    env::current_exe().is_ok_and(|current_exe| current_exe == exe)
+}
@@ -0,0 +1,200 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use anyhow::Result;
+use log::debug;
+use log::warn;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::config::DutyCycleConfig;
+use crate::config::DutyCyclePolicy;
+use crate::storage::write_atomically;
+
+/// Keeps track of how long the motor has been running within a sliding window
+/// so that it does not overheat.
+///
+/// The runs are persisted between invocations. A run is recorded with its
+/// longest possible duration when it starts and corrected when it ends, so an
+/// interrupted program errs on the side of letting the motor cool down.
+#[derive(Debug)]
+pub(crate) struct DutyCycle {
+    state_file: PathBuf,
+    window: Duration,
+    // The longest time the motor may run within the window
+    max_run_time: Duration,
+    // What to do with a movement while the motor is cooling down
+    policy: DutyCyclePolicy,
+    runs: Vec<MotorRun>,
+}
+
+/// A single run of the motor.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+struct MotorRun {
+    #[serde(with = "crate::storage::rfc3339")]
+    started_at: SystemTime,
+    duration_secs: f32,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct DutyCycleState {
+    #[serde(default)]
+    runs: Vec<MotorRun>,
+}
+
+impl DutyCycle {
+    /// Creates a new [`DutyCycle`] instance, restoring the past runs from the
+    /// state file.
+    ///
+    /// # Panics
+    /// Panics if the state file exists but cannot be read.
+    pub(crate) fn new(config: &DutyCycleConfig) -> Self {
+        let window = Duration::from_secs(config.window_mins * 60);
+        let state = load_state(&config.state_file).expect("duty cycle state must be readable");
+        Self {
+            state_file: config.state_file.clone(),
+            window,
+            max_run_time: window.mul_f32(config.max_duty_cycle.clamp(0.0, 1.0)),
+            policy: config.policy,
+            runs: state.runs,
+        }
+    }
+
+    /// The time the motor may still run right now without exceeding the duty
+    /// cycle.
+    pub(crate) fn run_budget(&self) -> Duration {
+        let run_budget = self.run_budget_at(SystemTime::now());
+        debug!("Motor run budget is {run_budget:?}");
+        run_budget
+    }
+
+    /// What to do with a movement while the motor is cooling down.
+    pub(crate) fn policy(&self) -> DutyCyclePolicy {
+        self.policy
+    }
+
+    /// The time until the motor has cooled down enough to run for `run_time`,
+    /// zero if it may run right away. Runs longer than the duty cycle allows
+    /// at all may start once the full budget is available.
+    pub(crate) fn cooldown(
+        &self,
+        run_time: Duration,
+    ) -> Duration {
+        let now = SystemTime::now();
+        let run_time = run_time.min(self.max_run_time);
+        let can_start_after =
+            |secs: u64| self.run_budget_at(now + Duration::from_secs(secs)) >= run_time;
+        if can_start_after(0) {
+            return Duration::ZERO;
+        }
+        // All runs have ended, so the budget only grows while they leave the
+        // window. Once the window has passed entirely, the full budget is
+        // available again.
+        let (mut lower_secs, mut upper_secs) = (0, self.window.as_secs() + 1);
+        while upper_secs - lower_secs > 1 {
+            let secs = (lower_secs + upper_secs) / 2;
+            if can_start_after(secs) {
+                upper_secs = secs;
+            } else {
+                lower_secs = secs;
+            }
+        }
+        Duration::from_secs(upper_secs)
+    }
+
+    /// Records that the motor started running for at most `max_duration`.
+    pub(crate) fn run_started(
+        &mut self,
+        started_at: SystemTime,
+        max_duration: Duration,
+    ) {
+        self.prune(started_at);
+        self.runs.push(MotorRun {
+            started_at,
+            duration_secs: max_duration.as_secs_f32(),
+        });
+        self.persist();
+    }
+
+    /// Records the actual duration of the run started last.
+    pub(crate) fn run_stopped(
+        &mut self,
+        duration: Duration,
+    ) {
+        if let Some(run) = self.runs.last_mut() {
+            run.duration_secs = duration.as_secs_f32();
+        }
+        self.persist();
+    }
+
+    /// The human readable duty cycle limit, e.g. `10% over 20m`.
+    pub(crate) fn describe_limit(&self) -> String {
+        format!(
+            "{:.0}% over {}m",
+            self.max_run_time.as_secs_f32() / self.window.as_secs_f32().max(1.0) * 100.0,
+            self.window.as_secs() / 60
+        )
+    }
+
+    fn run_budget_at(
+        &self,
+        time: SystemTime,
+    ) -> Duration {
+        let window_start = time
+            .checked_sub(self.window)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let run_time: Duration = self
+            .runs
+            .iter()
+            .map(|run| {
+                let end = run.started_at + Duration::from_secs_f32(run.duration_secs.max(0.0));
+                end.min(time)
+                    .duration_since(run.started_at.max(window_start))
+                    .unwrap_or_default()
+            })
+            .sum();
+        self.max_run_time.saturating_sub(run_time)
+    }
+
+    /// Forgets the runs which ended before the window.
+    fn prune(
+        &mut self,
+        now: SystemTime,
+    ) {
+        let window_start = now
+            .checked_sub(self.window)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        self.runs.retain(|run| {
+            run.started_at + Duration::from_secs_f32(run.duration_secs.max(0.0)) > window_start
+        });
+    }
+
+    fn persist(&self) {
+        let state = DutyCycleState {
+            runs: self.runs.clone(),
+        };
+        let result = toml::to_string(&state)
+            .map_err(anyhow::Error::from)
+            .and_then(|raw_state| write_atomically(&self.state_file, raw_state.as_bytes()));
+        if let Err(e) = result {
+            warn!(
+                "Could not persist the motor duty cycle to {:?}: {e}",
+                self.state_file
+            );
+        }
+    }
+}
+
+/// Loads the past motor runs, of which there are none if they have never been
+/// persisted.
+fn load_state(path: &Path) -> Result<DutyCycleState> {
+    match fs::read_to_string(path) {
+        Ok(raw_state) => Ok(toml::from_str(&raw_state)?),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(DutyCycleState::default()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,114 @@
+use std::thread::sleep;
+use std::time::Duration;
+
+use log::warn;
+use rppal::gpio::Gpio;
+use rppal::gpio::OutputPin;
+
+use crate::config::FeedbackConfig;
+
+// How fast the LED blinks after an error
+const ERROR_BLINK_FREQUENCY_HZ: f64 = 2.0;
+
+/// Something worth beeping about. Each has its own pattern of beeps so they
+/// can be told apart without looking at the desk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Beep {
+    /// An automated movement is about to start.
+    Warning,
+    /// The table starts moving.
+    MovementStarted,
+    /// The table arrived at its target height.
+    Arrived,
+    /// A movement or calibration failed, or the table stopped short of its
+    /// target height.
+    Error,
+    /// The calibration completed.
+    CalibrationDone,
+}
+
+impl Beep {
+    /// The beep's pattern as pairs of on and off times in milliseconds.
+    fn pattern(self) -> &'static [(u64, u64)] {
+        match self {
+            Beep::Warning => &[(100, 100), (100, 100), (100, 100)],
+            Beep::MovementStarted => &[(50, 0)],
+            Beep::Arrived => &[(80, 80), (80, 0)],
+            Beep::Error => &[(600, 0)],
+            Beep::CalibrationDone => &[(80, 80), (80, 80), (300, 0)],
+        }
+    }
+}
+
+/// The state shown by the status LED.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Status {
+    /// The LED is off.
+    Idle,
+    /// The LED is on.
+    Moving,
+    /// The LED blinks until the next movement.
+    Error,
+}
+
+/// Optional buzzer and status LED giving feedback about what the table does.
+/// Without any configured outputs, all feedback is a no-op.
+#[derive(Debug)]
+pub(crate) struct Feedback {
+    buzzer: Option<OutputPin>,
+    led: Option<OutputPin>,
+}
+
+impl Feedback {
+    /// Claims the configured output pins.
+    ///
+    /// # Panics
+    /// Panics if the pins cannot be initialised.
+    pub(crate) fn new(config: &FeedbackConfig) -> Self {
+        let claim = |pin: u8, name: &str| {
+            Gpio::new()
+                .expect("gpio to be available")
+                .get(pin)
+                .unwrap_or_else(|e| panic!("{name} pin to be available: {e}"))
+                .into_output_low()
+        };
+        Self {
+            buzzer: config.buzzer_pin.map(|pin| claim(pin, "buzzer")),
+            led: config.led_pin.map(|pin| claim(pin, "LED")),
+        }
+    }
+
+    /// Plays the beep's pattern on the buzzer, blocking until it is done.
+    pub(crate) fn beep(
+        &mut self,
+        beep: Beep,
+    ) {
+        let Some(buzzer) = &mut self.buzzer else {
+            return;
+        };
+        for &(on_ms, off_ms) in beep.pattern() {
+            buzzer.set_high();
+            sleep(Duration::from_millis(on_ms));
+            buzzer.set_low();
+            sleep(Duration::from_millis(off_ms));
+        }
+    }
+
+    /// Shows the status on the LED.
+    pub(crate) fn set_status(
+        &mut self,
+        status: Status,
+    ) {
+        let Some(led) = &mut self.led else {
+            return;
+        };
+        let result = match status {
+            Status::Idle => led.clear_pwm().map(|()| led.set_low()),
+            Status::Moving => led.clear_pwm().map(|()| led.set_high()),
+            Status::Error => led.set_pwm_frequency(ERROR_BLINK_FREQUENCY_HZ, 0.5),
+        };
+        if let Err(e) = result {
+            warn!("Could not set the status LED to {status:?}: {e}");
+        }
+    }
+}
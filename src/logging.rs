@@ -0,0 +1,244 @@
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use anyhow::Result;
+use env_logger::filter::Filter;
+use log::LevelFilter;
+use log::Log;
+use log::Metadata;
+use log::Record;
+
+use crate::config::LogFormat;
+use crate::config::LoggingConfig;
+use crate::systemd;
+
+// The socket of the local syslog daemon
+const SYSLOG_SOCKET: &str = "/dev/log";
+// The syslog facility for user-level messages
+const SYSLOG_FACILITY_USER: u8 = 1;
+
+/// Sets up logging to stderr and the configured outputs.
+///
+/// The level is taken from the `-d` count if given, otherwise from the
+/// configuration. Module levels in the configuration and the `RUST_LOG`
+/// environment variable are applied on top, in that order.
+pub(crate) fn init(
+    debug: u8,
+    config: &LoggingConfig,
+) -> Result<()> {
+    let level = match debug {
+        0 => config.level,
+        1 => LevelFilter::Warn,  // -d
+        2 => LevelFilter::Info,  // -dd
+        _ => LevelFilter::Debug, // -ddd
+    };
+    let mut filter = env_logger::filter::Builder::new();
+    filter.filter_level(level);
+    for (module, level) in &config.modules {
+        filter.filter_module(module, *level);
+    }
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        filter.parse(&filters);
+    }
+    // Unavailable outputs must not keep the table from moving, and there is no
+    // logger to report them to yet
+    let file = config.file.as_ref().and_then(|path| {
+        RotatingFile::open(
+            path.clone(),
+            config.max_file_size_mb * 1024 * 1024,
+            config.max_files,
+        )
+        .map(Mutex::new)
+        .map_err(|e| eprintln!("Not logging to {path:?}: {e:#}"))
+        .ok()
+    });
+    let syslog = config.syslog.then(connect_syslog).and_then(|result| {
+        result
+            .map_err(|e| eprintln!("Not logging to syslog: {e:#}"))
+            .ok()
+    });
+    let logger = Logger {
+        filter: filter.build(),
+        format: config.format,
+        journal: systemd::logs_to_journal(),
+        file,
+        syslog,
+    };
+    log::set_max_level(logger.filter.filter());
+    log::set_boxed_logger(Box::new(logger))?;
+    Ok(())
+}
+
+fn connect_syslog() -> Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(SYSLOG_SOCKET)?;
+    Ok(socket)
+}
+
+/// Writes the log records passing the filter to all outputs.
+#[derive(Debug)]
+struct Logger {
+    filter: Filter,
+    format: LogFormat,
+    // Whether stderr is connected to the systemd journal
+    journal: bool,
+    file: Option<Mutex<RotatingFile>>,
+    syslog: Option<UnixDatagram>,
+}
+
+impl Log for Logger {
+    fn enabled(
+        &self,
+        metadata: &Metadata,
+    ) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(
+        &self,
+        record: &Record,
+    ) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let line = self.format_line(record);
+        let stderr_line = if self.journal && self.format == LogFormat::Text {
+            // journald adds the time and reads the level from the prefix
+            format!(
+                "<{}>{}",
+                systemd::journal_priority(record.level()),
+                record.args()
+            )
+        } else {
+            line.clone()
+        };
+        // There is nowhere left to report failing log outputs to
+        let _ = writeln!(io::stderr(), "{stderr_line}");
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+            let _ = file.write_line(&line);
+        }
+        if let Some(syslog) = &self.syslog {
+            let priority = SYSLOG_FACILITY_USER * 8 + systemd::journal_priority(record.level());
+            let _ = syslog.send(
+                format!(
+                    "<{priority}>standup[{}]: {}: {}",
+                    process::id(),
+                    record.target(),
+                    record.args()
+                )
+                .as_bytes(),
+            );
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap_or_else(|e| e.into_inner()).file.flush();
+        }
+    }
+}
+
+impl Logger {
+    fn format_line(
+        &self,
+        record: &Record,
+    ) -> String {
+        let timestamp = humantime::format_rfc3339_seconds(SystemTime::now());
+        match self.format {
+            LogFormat::Text => format!(
+                "[{timestamp} {:<5} {}] {}",
+                record.level(),
+                record.target(),
+                record.args()
+            ),
+            LogFormat::Json => serde_json::json!({
+                "timestamp": timestamp.to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            })
+            .to_string(),
+        }
+    }
+}
+
+/// A log file which is rotated once it exceeds its maximum size, keeping a
+/// number of rotated files next to it, e.g. `standup.log.1`.
+#[derive(Debug)]
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: u32,
+}
+
+impl RotatingFile {
+    fn open(
+        path: PathBuf,
+        max_size: u64,
+        max_files: u32,
+    ) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn write_line(
+        &mut self,
+        line: &str,
+    ) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 + 1 > self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{line}")?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// Shifts the rotated files by one, dropping the oldest, and starts a new
+    /// file.
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated_path = |index: u32| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{index}"));
+            PathBuf::from(path)
+        };
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.max_files).rev() {
+                let from = rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
@@ -6,29 +6,92 @@
 #![deny(missing_copy_implementations)]
 #![warn(clippy::doc_markdown)]
 
+mod buttons;
+mod calibration;
 mod config;
+mod control;
+mod duty_cycle;
+mod feedback;
+mod logging;
+mod metrics;
 mod motor;
 mod movement;
+mod presence;
+mod presets;
 mod primitives;
 mod sensor;
+mod sensorless;
+mod server;
+mod shutdown;
+mod stats;
+mod storage;
+mod systemd;
 mod table;
+mod telemetry;
+mod tui;
+mod warning;
+mod webhooks;
 
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
 use std::path::PathBuf;
-use std::sync::mpsc::channel;
 use std::thread::sleep;
 use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
 
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Days;
+use clap::builder::PossibleValuesParser;
+use clap::error::ErrorKind;
+use clap::value_parser;
+use clap::CommandFactory;
 use clap::Parser;
 use clap::Subcommand;
-use env_logger::Builder;
+use clap::ValueEnum;
+use clap_complete::Shell;
 use log::info;
-use log::LevelFilter;
-use simple_signal::Signal;
+use log::warn;
 
+use crate::buttons::Buttons;
+use crate::calibration::CalibrationStore;
 use crate::config::Config;
+use crate::config::ConflictPolicy;
+use crate::control::InstanceLock;
+use crate::control::PidFile;
+use crate::motor::MoveDirection;
 use crate::movement::Movement;
 use crate::primitives::Centimeter;
+use crate::server::Client;
+use crate::server::Server;
+use crate::shutdown::Shutdown;
+use crate::stats::compute_stats;
+use crate::stats::goal_progress;
+use crate::stats::local_time;
+use crate::stats::GoalProgress;
+use crate::systemd::ServiceScope;
 use crate::table::StandingDesk;
+use crate::telemetry::format_hours_minutes;
+use crate::telemetry::local_date;
+use crate::telemetry::posture_intervals;
+use crate::telemetry::summarise_by_day;
+use crate::telemetry::summarise_by_week;
+use crate::telemetry::PostureInterval;
+use crate::telemetry::Telemetry;
+use crate::telemetry::Trigger;
+use crate::tui::Tui;
+use crate::warning::MAX_SNOOZE_MINS;
+
+// The hidden command printing the preset names in a config, which the shell
+// completions call whenever completing a preset
+const COMPLETE_PRESETS: &str = "__complete-presets";
+// Stands in for the preset names in the generated completions until it is
+// replaced with a call to `__complete-presets`
+const PRESETS_PLACEHOLDER: &str = "__standup_presets__";
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -40,49 +103,329 @@ struct Cli {
     #[arg(short, long, action = clap::ArgAction::Count)]
     debug: u8,
 
-    /// The path to the config file
+    /// The path to the config file, required by all commands but
+    /// `completions` and `man`
     #[arg(short, long, value_name = "FILE")]
-    config: PathBuf,
+    config: Option<PathBuf>,
+
+    /// What triggered the command, recorded with the movements
+    #[arg(long, value_enum, default_value_t = Trigger::Cli)]
+    trigger: Trigger,
+
+    /// What to do if another process already controls the desk, overriding
+    /// the configuration
+    #[arg(long, value_enum)]
+    on_conflict: Option<ConflictPolicy>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    Calibrate,
+    Calibrate {
+        /// The current ambient temperature in degrees Celsius, recorded with
+        /// the calibration
+        #[arg(long)]
+        temperature: Option<f32>,
+    },
+    /// Inspect and manage past calibrations
+    Calibration {
+        #[command(subcommand)]
+        command: CalibrationCommands,
+    },
     Sit,
     Stand,
     #[command(arg_required_else_help = true)]
     MoveTo {
         height: u8,
     },
+    /// Move the desk to a preset, e.g. `sitting`, `standing` or a configured
+    /// one
+    #[command(arg_required_else_help = true)]
+    Preset {
+        name: String,
+    },
+    /// Move the desk up by a number of centimeters
+    #[command(arg_required_else_help = true)]
+    Up {
+        distance: u8,
+    },
+    /// Move the desk down by a number of centimeters
+    #[command(arg_required_else_help = true)]
+    Down {
+        distance: u8,
+    },
+    /// Move the desk in a direction for a short time, for fine adjustments
+    #[command(arg_required_else_help = true)]
+    Jog {
+        /// The time in milliseconds to move for
+        #[arg(long, default_value_t = 500)]
+        ms: u64,
+        direction: MoveDirection,
+    },
     TestSensor,
+    /// Stop a movement in progress in another process
+    Stop,
+    /// Cancel the scheduled movement currently being warned about
+    Cancel,
+    /// Skip scheduled movements for a while, including the one currently being
+    /// warned about
+    Snooze {
+        /// The number of minutes to skip scheduled movements for
+        #[arg(long, default_value_t = 30, value_parser = value_parser!(u64).range(..=MAX_SNOOZE_MINS))]
+        mins: u64,
+    },
+    /// Control the desk with the configured buttons until stopped
+    Buttons,
+    /// Control the desk interactively in the terminal
+    Tui,
+    /// Accept commands on the control socket until stopped, e.g. as a systemd
+    /// service
+    Serve,
+    /// Install systemd unit files running `serve` with this config
+    InstallService {
+        /// Install a system-wide service instead of a user service
+        #[arg(long)]
+        system: bool,
+        /// Print the unit files instead of installing them
+        #[arg(long)]
+        print: bool,
+    },
+    /// Print shell completions, including the presets in the config
+    #[command(arg_required_else_help = true)]
+    Completions {
+        shell: Shell,
+    },
+    /// Print the man page
+    Man,
+    /// Record the current height, e.g. periodically from a cronjob
+    Sample,
+    /// Summarise the time spent sitting and standing per day and week
+    History {
+        /// The number of past days to summarise
+        #[arg(long, default_value_t = 14)]
+        days: u32,
+    },
+    /// Show statistics about the time spent sitting and standing
+    Stats {
+        /// The number of past days to include, starting with today
+        #[arg(long, default_value_t = 1)]
+        days: u32,
+        /// The output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    /// Move to the standing position if the daily standing goal is behind
+    /// schedule, e.g. periodically from a cronjob
+    Remind,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Table,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum CalibrationCommands {
+    /// List all past calibrations
+    List,
+    /// Show a calibration, the current one if no id is given
+    Show { id: Option<String> },
+    /// Make a past calibration the current one
+    #[command(arg_required_else_help = true)]
+    Rollback { id: String },
 }
 
-fn main() {
+fn main() -> Result<()> {
+    // Called by the shell completions, so not part of the command line
+    let mut args = env::args_os().skip(1);
+    if args.next().is_some_and(|arg| arg == COMPLETE_PRESETS) {
+        // Errors would end up in the middle of the command line being completed
+        let config = args
+            .next()
+            .and_then(|config_file| Config::load(config_file).ok());
+        for (name, _) in config.iter().flat_map(Config::all_presets) {
+            println!("{name}");
+        }
+        return Ok(());
+    }
+
     let cli = Cli::parse();
-    let config = Config::load(cli.config).expect("be able to load configuration");
-    let (shutdown_tx, shutdown_rx) = channel::<()>();
 
-    let mut builder = Builder::new();
+    // These only describe the command line and must work without a config
+    if let Commands::Completions { shell } = cli.command {
+        // The config only contributes the preset names
+        let config = cli.config.as_ref().and_then(|config_file| {
+            Config::load(config_file)
+                .map_err(|e| eprintln!("Not completing preset names: {e:#}"))
+                .ok()
+        });
+        print_completions(shell, cli.config.as_deref(), config.as_ref());
+        return Ok(());
+    }
 
-    let builder = match cli.debug {
-        0 => builder.filter_level(LevelFilter::Error),
-        1 => builder.filter_level(LevelFilter::Warn), // -c
-        2 => builder.filter_level(LevelFilter::Info), // -cc
-        _ => builder.filter_level(LevelFilter::Debug), // -ccc
-    };
-    builder.init();
+    if matches!(cli.command, Commands::Man) {
+        clap_mangen::Man::new(Cli::command())
+            .render(&mut io::stdout())
+            .expect("be able to print the man page");
+        return Ok(());
+    }
 
-    simple_signal::set_handler(&[Signal::Int, Signal::Term], move |_| {
-        println!("Shutting down");
-        shutdown_tx
-            .send(())
-            .expect("be able to send a shutdown signal")
+    let config_file = cli.config.clone().unwrap_or_else(|| {
+        Cli::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "the config file is required, pass it with --config <FILE>",
+            )
+            .exit()
     });
+    let config = Config::load(&config_file).expect("be able to load configuration");
+
+    logging::init(cli.debug, &config.logging).expect("be able to set up logging");
 
-    let mut table = StandingDesk::new(config, shutdown_rx);
+    if let Commands::Calibration { command } = cli.command {
+        let store = CalibrationStore::new(&config.sensor.calibration_file);
+        run_calibration_command(&store, command).expect("calibration command to work");
+        return Ok(());
+    }
+    if let Commands::History { days } = cli.command {
+        print_history(&config, days).expect("history to be available");
+        return Ok(());
+    }
+    if let Commands::Stats { days, format } = cli.command {
+        print_stats(&config, days, format).expect("statistics to be available");
+        return Ok(());
+    }
+    if matches!(cli.command, Commands::Remind) {
+        let goal_progress = today_goal_progress(&config).expect("statistics to be available");
+        match goal_progress {
+            None => return Err(anyhow!("no daily standing goal configured")),
+            Some(goal_progress) if !goal_progress.behind_schedule => {
+                info!("Standing goal on schedule: {goal_progress:?}");
+                return Ok(());
+            }
+            Some(goal_progress) => info!("Standing goal behind schedule: {goal_progress:?}"),
+        }
+    }
+
+    if matches!(cli.command, Commands::Stop) {
+        match control::stop(&config.control).expect("stopping the desk to work") {
+            Some(pid) => println!("Stopped the movement of process {pid}"),
+            None => println!("The desk is not being controlled by another process"),
+        }
+        return Ok(());
+    }
+    if let Commands::InstallService { system, print } = cli.command {
+        let scope = if system {
+            ServiceScope::System
+        } else {
+            ServiceScope::User
+        };
+        install_service(&config_file, &config, scope, print)
+            .expect("be able to install the service");
+        return Ok(());
+    }
+    if matches!(cli.command, Commands::Cancel) {
+        warning::snooze(&config.warning, Duration::ZERO).expect("be able to cancel movements");
+        println!("Cancelled pending scheduled movements");
+        return Ok(());
+    }
+    if let Commands::Snooze { mins } = cli.command {
+        warning::snooze(
+            &config.warning,
+            Duration::from_secs(mins.saturating_mul(60)),
+        )
+        .expect("be able to snooze movements");
+        println!("Snoozed scheduled movements for {mins} minutes");
+        return Ok(());
+    }
+
+    let shutdown = Shutdown::on_signals();
+    let standing_threshold = config.table.standing_threshold();
+    // A running service controls the desk, so let it carry out the command
+    if let Some(service_command) = service_command(&cli.command) {
+        if let Some(mut client) =
+            Client::connect(&config.control).expect("be able to connect to the service")
+        {
+            run_on_service(
+                &mut client,
+                &service_command,
+                &cli.command,
+                cli.trigger,
+                standing_threshold,
+                &shutdown,
+            )?;
+            return Ok(());
+        }
+    }
+    let _lock = InstanceLock::acquire(
+        &config.control,
+        cli.on_conflict.unwrap_or(config.control.on_conflict),
+        &shutdown,
+    )
+    .context("cannot control the desk")?;
+    let _pid_file =
+        PidFile::create(&config.control.pid_file).expect("be able to write the pid file");
+    // Short-lived commands would only hold on to the port, keeping the
+    // long-running ones from serving the metrics
+    if matches!(
+        cli.command,
+        Commands::Serve | Commands::Buttons | Commands::Tui
+    ) {
+        if let Some(metrics_config) = &config.metrics {
+            // The desk must stay usable without metrics
+            if let Err(e) = metrics::serve(metrics_config) {
+                warn!(
+                    "Not serving metrics at {}: {e:#}",
+                    metrics_config.listen_address
+                );
+            }
+        }
+    }
+    let preset_height = match &cli.command {
+        Commands::Preset { name } => Some(config.preset(name).ok_or_else(|| {
+            let names: Vec<String> = config
+                .all_presets()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect();
+            anyhow!(
+                "unknown preset {name:?}, available presets are {}",
+                names.join(", ")
+            )
+        })?),
+        _ => None,
+    };
+    let mut buttons = matches!(cli.command, Commands::Buttons)
+        .then(|| Buttons::new(&config).expect("buttons to be available"));
+    let mut tui = matches!(cli.command, Commands::Tui).then(|| Tui::new(&config));
+    let mut server = matches!(cli.command, Commands::Serve)
+        .then(|| Server::bind(&config).expect("be able to listen on the control socket"));
+    let mut table = StandingDesk::new(config, shutdown.clone());
+    table.set_trigger(cli.trigger);
+    // The terminal UI can calibrate new desks itself
+    if !matches!(cli.command, Commands::Calibrate { .. } | Commands::Tui) && !table.is_calibrated()
+    {
+        return Err(anyhow!(
+            "no calibration data found at {:?}, please run `standup calibrate` first",
+            table.calibration_file()
+        ));
+    }
     match cli.command {
-        Commands::Calibrate => {
-            table.calibrate().expect("calibration to work");
+        Commands::Calibrate { temperature } => {
+            // Being stopped midway is expected, e.g. with Ctrl-C. Returning
+            // the error drops the desk first, delivering its webhooks.
+            table.calibrate(temperature)?;
+        }
+        Commands::Calibration { .. }
+        | Commands::History { .. }
+        | Commands::Stats { .. }
+        | Commands::Stop
+        | Commands::Cancel
+        | Commands::Snooze { .. }
+        | Commands::InstallService { .. }
+        | Commands::Completions { .. }
+        | Commands::Man => {
+            unreachable!("handled above")
         }
         Commands::Sit => {
             table
@@ -99,15 +442,404 @@ fn main() {
                 .move_to_height(Centimeter(height))
                 .expect("moving to height to work");
         }
+        Commands::Preset { name } => {
+            if let Some(height) = preset_height {
+                table
+                    .move_to_height(height)
+                    .unwrap_or_else(|e| panic!("moving to preset {name} to work: {e:?}"));
+            }
+        }
+        Commands::Up { distance } => {
+            table
+                .move_by(MoveDirection::Up, Centimeter(distance))
+                .expect("moving up to work");
+        }
+        Commands::Down { distance } => {
+            table
+                .move_by(MoveDirection::Down, Centimeter(distance))
+                .expect("moving down to work");
+        }
+        Commands::Jog { ms, direction } => {
+            let start = Instant::now();
+            let duration = Duration::from_millis(ms);
+            table
+                .jog(direction, || start.elapsed() < duration)
+                .expect("jogging to work");
+        }
         Commands::TestSensor => {
             info!("Testing distance sensor for about 5s");
             let mut i = 0;
-            while i < 50 {
+            while i < 50 && !shutdown.is_requested() {
                 sleep(Duration::from_millis(100));
                 let current_height = table.get_measurement().unwrap().0;
                 info!("Height: {current_height:?}");
                 i += 1;
             }
         }
+        Commands::Remind => {
+            let height = table
+                .get_measurement()
+                .expect("measuring the height to work");
+            if height < standing_threshold {
+                table.set_trigger(Trigger::Reminder);
+                table
+                    .move_to_standing()
+                    .expect("moving to standing position to work");
+            }
+        }
+        Commands::Buttons => {
+            table.set_trigger(Trigger::Button);
+            if let Some(buttons) = &mut buttons {
+                buttons.run(&mut table, &shutdown);
+            }
+        }
+        Commands::Tui => {
+            if let Some(tui) = &mut tui {
+                tui.run(&mut table, &shutdown)
+                    .expect("the terminal UI to work");
+            }
+        }
+        Commands::Serve => {
+            if let Some(server) = &mut server {
+                server.run(&mut table, &shutdown);
+            }
+        }
+        Commands::Sample => {
+            let height = table
+                .record_height_sample()
+                .expect("measuring the height to work");
+            info!("Height: {height:?}");
+        }
+    };
+    Ok(())
+}
+
+/// The command for a running service carrying out `command`, if it can.
+fn service_command(command: &Commands) -> Option<String> {
+    match command {
+        Commands::Sit => Some("sit".to_string()),
+        Commands::Stand => Some("stand".to_string()),
+        Commands::MoveTo { height } => Some(format!("move-to {height}")),
+        Commands::Preset { name } => Some(format!("preset {name}")),
+        Commands::Up { distance } => Some(format!("up {distance}")),
+        Commands::Down { distance } => Some(format!("down {distance}")),
+        Commands::Sample => Some("sample".to_string()),
+        // Only sent if the desk is not standing already
+        Commands::Remind => Some("stand".to_string()),
+        _ => None,
+    }
+}
+
+/// Carries out `command` by sending `service_command` to the running service
+/// the client is connected to.
+fn run_on_service(
+    client: &mut Client,
+    service_command: &str,
+    command: &Commands,
+    trigger: Trigger,
+    standing_threshold: Centimeter,
+    shutdown: &Shutdown,
+) -> Result<()> {
+    let trigger = if matches!(command, Commands::Remind) {
+        let height = Centimeter(client.send("height", shutdown)?.parse()?);
+        if height >= standing_threshold {
+            info!("Already standing at {height:?}");
+            return Ok(());
+        }
+        Trigger::Reminder
+    } else {
+        trigger
+    };
+    client.send(&format!("trigger {}", trigger.name()), shutdown)?;
+    info!("Sending {service_command:?} to the service");
+    let height = client.send(service_command, shutdown)?;
+    info!("Height: {height}cm");
+    Ok(())
+}
+
+/// Writes the unit files for running `standup serve` with the config at
+/// `config_file`, or prints them.
+fn install_service(
+    config_file: &Path,
+    config: &Config,
+    scope: ServiceScope,
+    print: bool,
+) -> Result<()> {
+    let unit_files = systemd::unit_files(config_file, &config.control, scope)?;
+    if print {
+        for (name, contents) in unit_files {
+            println!("# {name}\n{contents}");
+        }
+        return Ok(());
+    }
+    let unit_dir = systemd::unit_dir(scope)?;
+    fs::create_dir_all(&unit_dir)?;
+    for (name, contents) in unit_files {
+        let path = unit_dir.join(name);
+        fs::write(&path, contents)?;
+        println!("Wrote {}", path.display());
+    }
+    let systemctl = match scope {
+        ServiceScope::User => "systemctl --user",
+        ServiceScope::System => "systemctl",
+    };
+    println!("Enable the service with `{systemctl} daemon-reload && {systemctl} enable --now standup.socket`");
+    Ok(())
+}
+
+/// Prints completions for `shell`, completing the preset names in the config
+/// if given.
+///
+/// The bash, zsh and fish completions read the preset names from the config
+/// file whenever completing, so that presets added later are completed as
+/// well. The other shells complete the presets known right now.
+fn print_completions(
+    shell: Shell,
+    config_file: Option<&Path>,
+    config: Option<&Config>,
+) {
+    let mut command = Cli::command();
+    let bin_name = command.get_name().to_string();
+    let config_file = config_file
+        .filter(|_| config.is_some() && matches!(shell, Shell::Bash | Shell::Zsh | Shell::Fish))
+        .map(|config_file| fs::canonicalize(config_file).unwrap_or(config_file.to_path_buf()));
+    let preset_names: Vec<String> = match (&config_file, config) {
+        (Some(_), _) => vec![PRESETS_PLACEHOLDER.to_string()],
+        (None, Some(config)) => config
+            .all_presets()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect(),
+        (None, None) => vec![],
+    };
+    if !preset_names.is_empty() {
+        command = command.mut_subcommand("preset", |preset| {
+            preset.mut_arg("name", |name| {
+                name.value_parser(PossibleValuesParser::new(preset_names))
+            })
+        });
+    }
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut command, &bin_name, &mut script);
+    let mut script = String::from_utf8(script).expect("completions to be valid UTF-8");
+    if let Some(config_file) = config_file {
+        script = complete_presets_dynamically(shell, &script, &bin_name, &config_file);
+    }
+    print!("{script}");
+}
+
+/// Replaces the placeholder for the preset names in the completions with a
+/// call to `__complete-presets`.
+fn complete_presets_dynamically(
+    shell: Shell,
+    script: &str,
+    bin_name: &str,
+    config_file: &Path,
+) -> String {
+    let config_file = config_file.display().to_string();
+    // Quoted for POSIX shells
+    let command = format!(
+        "{bin_name} {COMPLETE_PRESETS} '{}' 2>/dev/null",
+        config_file.replace('\'', r"'\''")
+    );
+    match shell {
+        Shell::Bash => script.replace(PRESETS_PLACEHOLDER, &format!("$({command})")),
+        Shell::Zsh => {
+            let script = script.replace(
+                &format!("({PRESETS_PLACEHOLDER})"),
+                &format!("_{bin_name}_presets"),
+            );
+            let function = format!(
+                "_{bin_name}_presets() {{\n    local -a presets\n    presets=(${{(f)\"$({command})\"}})\n    \
+                 compadd -a presets\n}}\n"
+            );
+            // Defined before the completion function runs for the first time
+            match script.split_once('\n') {
+                Some((compdef, rest)) => format!("{compdef}\n\n{function}{rest}"),
+                None => script,
+            }
+        }
+        Shell::Fish => {
+            let command = format!(
+                "{bin_name} {COMPLETE_PRESETS} '{}' 2>/dev/null",
+                config_file.replace('\\', r"\\").replace('\'', r"\'")
+            );
+            // Positional values are not completed for fish, so the
+            // placeholder is not used
+            format!(
+                "{script}complete -c {bin_name} -n \"__fish_{bin_name}_using_subcommand preset\" -f \
+                 -a \"({command})\"\n"
+            )
+        }
+        _ => script.to_string(),
+    }
+}
+
+fn run_calibration_command(
+    store: &CalibrationStore,
+    command: CalibrationCommands,
+) -> Result<()> {
+    match command {
+        CalibrationCommands::List => {
+            let current = store.current()?;
+            for entry in store.list()? {
+                let marker = if current.as_ref() == Some(&entry.data) {
+                    "*"
+                } else {
+                    " "
+                };
+                let data = &entry.data;
+                let temperature = data
+                    .metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.temperature_celsius)
+                    .map(|temperature| format!("{temperature:.1}°C"))
+                    .unwrap_or_else(|| "-".to_string());
+                println!(
+                    "{marker} {}  {}-{}cm  {:.6}s-{:.6}s  {temperature}",
+                    entry.id,
+                    data.min_height.into_inner(),
+                    data.max_height.into_inner(),
+                    data.min_height_echo_secs,
+                    data.max_height_echo_secs,
+                );
+            }
+        }
+        CalibrationCommands::Show { id } => {
+            let data = match id {
+                Some(id) => store.get(&id)?,
+                None => store.current()?.ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "no calibration data found at {:?}",
+                        store.calibration_file()
+                    )
+                })?,
+            };
+            print!("{}", toml::to_string(&data)?);
+        }
+        CalibrationCommands::Rollback { id } => {
+            store.rollback(&id)?;
+            println!("Rolled back calibration to {id}");
+        }
+    }
+    Ok(())
+}
+
+fn print_history(
+    config: &Config,
+    days: u32,
+) -> Result<()> {
+    let telemetry = Telemetry::new(&config.telemetry);
+    let events = telemetry.events()?;
+    let now = SystemTime::now();
+    let mut daily_summaries = summarise_by_day(
+        &events,
+        config.table.standing_threshold(),
+        Duration::from_secs(config.telemetry.max_gap_mins * 60),
+        now,
+    );
+    let first_day = local_date(now) - Days::new(days.saturating_sub(1) as u64);
+    let daily_summaries = daily_summaries.split_off(&first_day);
+
+    println!(
+        "{:<10}  {:>9}  {:>9}  {:>5}",
+        "Day", "Sitting", "Standing", "Moves"
+    );
+    for (date, summary) in &daily_summaries {
+        println!(
+            "{:<10}  {:>9}  {:>9}  {:>5}",
+            date.to_string(),
+            format_hours_minutes(summary.sitting),
+            format_hours_minutes(summary.standing),
+            summary.movements
+        );
+    }
+    println!();
+    println!(
+        "{:<10}  {:>9}  {:>9}  {:>5}",
+        "Week", "Sitting", "Standing", "Moves"
+    );
+    for (week, summary) in summarise_by_week(&daily_summaries) {
+        println!(
+            "{:<10}  {:>9}  {:>9}  {:>5}",
+            format!("{}-W{:02}", week.year(), week.week()),
+            format_hours_minutes(summary.sitting),
+            format_hours_minutes(summary.standing),
+            summary.movements
+        );
+    }
+    Ok(())
+}
+
+fn print_stats(
+    config: &Config,
+    days: u32,
+    format: OutputFormat,
+) -> Result<()> {
+    let now = SystemTime::now();
+    let first_day = local_date(now) - Days::new(days.saturating_sub(1) as u64);
+    let from = local_time(first_day, 0).unwrap_or(now);
+    let intervals = load_posture_intervals(config, now)?;
+    let stats = compute_stats(&intervals, from, now, config.goal.as_ref());
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&stats)?),
+        OutputFormat::Table => {
+            let hours_minutes = |secs| format_hours_minutes(Duration::from_secs(secs));
+            println!("Since                   {first_day}");
+            println!(
+                "Sitting                 {}",
+                hours_minutes(stats.sitting_secs)
+            );
+            println!(
+                "Standing                {}",
+                hours_minutes(stats.standing_secs)
+            );
+            println!(
+                "Longest sitting streak  {}",
+                hours_minutes(stats.longest_sitting_streak_secs)
+            );
+            if let Some(goal) = stats.goal {
+                println!(
+                    "Standing goal today     {} of {} ({}%)",
+                    hours_minutes(goal.standing_today_secs),
+                    hours_minutes(goal.goal_secs),
+                    (goal.standing_today_secs * 100)
+                        .checked_div(goal.goal_secs)
+                        .unwrap_or(100)
+                );
+                println!(
+                    "Expected by now         {}{}",
+                    hours_minutes(goal.expected_by_now_secs),
+                    if goal.behind_schedule {
+                        " (behind schedule)"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn today_goal_progress(config: &Config) -> Result<Option<GoalProgress>> {
+    let Some(goal) = &config.goal else {
+        return Ok(None);
     };
+    let now = SystemTime::now();
+    let intervals = load_posture_intervals(config, now)?;
+    Ok(Some(goal_progress(&intervals, goal, now)))
+}
+
+fn load_posture_intervals(
+    config: &Config,
+    now: SystemTime,
+) -> Result<Vec<PostureInterval>> {
+    let events = Telemetry::new(&config.telemetry).events()?;
+    Ok(posture_intervals(
+        &events,
+        config.table.standing_threshold(),
+        Duration::from_secs(config.telemetry.max_gap_mins * 60),
+        now,
+    ))
 }
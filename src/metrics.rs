@@ -0,0 +1,298 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use anyhow::Result;
+use log::debug;
+use log::info;
+use log::warn;
+use once_cell::sync::Lazy;
+
+use crate::config::MetricsConfig;
+use crate::motor::MoveDirection;
+use crate::primitives::Centimeter;
+use crate::telemetry::MovementOutcome;
+
+// The upper bounds in seconds of the buckets of the echo measurement latency
+// histogram
+const MEASUREMENT_LATENCY_BUCKETS: [f64; 8] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25];
+
+/// The metrics of this process, exported in the Prometheus text format.
+pub(crate) static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+/// Metrics about the table, its motor and its sensor.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    state: Mutex<MetricsState>,
+}
+
+#[derive(Debug, Default)]
+struct MetricsState {
+    current_height: Option<Centimeter>,
+    motor_run_secs_up: f64,
+    motor_run_secs_down: f64,
+    movements: BTreeMap<&'static str, u64>,
+    measurement_errors: u64,
+    // The number of measurements per latency bucket, the last entry counts the
+    // measurements exceeding the largest bucket
+    measurement_latency_buckets: [u64; MEASUREMENT_LATENCY_BUCKETS.len() + 1],
+    measurement_latency_sum_secs: f64,
+    calibrated_at: Option<SystemTime>,
+}
+
+impl Metrics {
+    /// Records the most recently measured height.
+    pub(crate) fn set_current_height(
+        &self,
+        height: Centimeter,
+    ) {
+        self.with_state(|state| state.current_height = Some(height));
+    }
+
+    /// Records the time the motor ran in the given direction.
+    pub(crate) fn add_motor_run_time(
+        &self,
+        direction: MoveDirection,
+        run_time: Duration,
+    ) {
+        self.with_state(|state| match direction {
+            MoveDirection::Up => state.motor_run_secs_up += run_time.as_secs_f64(),
+            MoveDirection::Down => state.motor_run_secs_down += run_time.as_secs_f64(),
+        });
+    }
+
+    /// Counts a movement with the given outcome.
+    pub(crate) fn count_movement(
+        &self,
+        outcome: MovementOutcome,
+    ) {
+        let outcome = match outcome {
+            MovementOutcome::Reached => "reached",
+            MovementOutcome::Missed => "missed",
+            MovementOutcome::Failed => "failed",
+            MovementOutcome::Skipped => "skipped",
+        };
+        self.with_state(|state| *state.movements.entry(outcome).or_default() += 1);
+    }
+
+    /// Records a single echo measurement, which took `latency` and may have
+    /// failed.
+    pub(crate) fn observe_measurement(
+        &self,
+        latency: Duration,
+        failed: bool,
+    ) {
+        let latency_secs = latency.as_secs_f64();
+        let bucket = MEASUREMENT_LATENCY_BUCKETS
+            .iter()
+            .position(|upper_bound| latency_secs <= *upper_bound)
+            .unwrap_or(MEASUREMENT_LATENCY_BUCKETS.len());
+        self.with_state(|state| {
+            state.measurement_latency_buckets[bucket] += 1;
+            state.measurement_latency_sum_secs += latency_secs;
+            if failed {
+                state.measurement_errors += 1;
+            }
+        });
+    }
+
+    /// Records when the current calibration was done.
+    pub(crate) fn set_calibrated_at(
+        &self,
+        calibrated_at: SystemTime,
+    ) {
+        self.with_state(|state| state.calibrated_at = Some(calibrated_at));
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub(crate) fn render(&self) -> String {
+        let state = self
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut out = String::new();
+        if let Some(height) = state.current_height {
+            write_metric(
+                &mut out,
+                "standup_height_cm",
+                "gauge",
+                "The most recently measured table height in centimeters.",
+                &[("", height.into_inner() as f64)],
+            );
+        }
+        write_metric(
+            &mut out,
+            "standup_motor_run_seconds_total",
+            "counter",
+            "The time the motor has been running per direction.",
+            &[
+                ("direction=\"up\"", state.motor_run_secs_up),
+                ("direction=\"down\"", state.motor_run_secs_down),
+            ],
+        );
+        let movements: Vec<(String, f64)> = ["reached", "missed", "failed", "skipped"]
+            .into_iter()
+            .map(|outcome| {
+                (
+                    format!("outcome=\"{outcome}\""),
+                    state.movements.get(outcome).copied().unwrap_or(0) as f64,
+                )
+            })
+            .collect();
+        write_metric(
+            &mut out,
+            "standup_movements_total",
+            "counter",
+            "The number of movements per outcome.",
+            &movements
+                .iter()
+                .map(|(labels, value)| (labels.as_str(), *value))
+                .collect::<Vec<_>>(),
+        );
+        write_metric(
+            &mut out,
+            "standup_sensor_measurement_errors_total",
+            "counter",
+            "The number of failed echo measurements.",
+            &[("", state.measurement_errors as f64)],
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP standup_sensor_measurement_duration_seconds The duration of single echo \
+             measurements."
+        );
+        let _ = writeln!(
+            out,
+            "# TYPE standup_sensor_measurement_duration_seconds histogram"
+        );
+        let mut cumulative_count = 0;
+        for (upper_bound, count) in MEASUREMENT_LATENCY_BUCKETS
+            .iter()
+            .zip(state.measurement_latency_buckets)
+        {
+            cumulative_count += count;
+            let _ = writeln!(
+                out,
+                "standup_sensor_measurement_duration_seconds_bucket{{le=\"{upper_bound}\"}} \
+                 {cumulative_count}"
+            );
+        }
+        cumulative_count += state.measurement_latency_buckets[MEASUREMENT_LATENCY_BUCKETS.len()];
+        let _ = writeln!(
+            out,
+            "standup_sensor_measurement_duration_seconds_bucket{{le=\"+Inf\"}} {cumulative_count}"
+        );
+        let _ = writeln!(
+            out,
+            "standup_sensor_measurement_duration_seconds_sum {}",
+            state.measurement_latency_sum_secs
+        );
+        let _ = writeln!(
+            out,
+            "standup_sensor_measurement_duration_seconds_count {cumulative_count}"
+        );
+
+        if let Some(calibrated_at) = state.calibrated_at {
+            let age = SystemTime::now()
+                .duration_since(calibrated_at)
+                .unwrap_or_default();
+            write_metric(
+                &mut out,
+                "standup_calibration_age_seconds",
+                "gauge",
+                "The time since the current calibration was done.",
+                &[("", age.as_secs_f64())],
+            );
+        }
+        out
+    }
+
+    fn with_state<F>(
+        &self,
+        update: F,
+    ) where
+        F: FnOnce(&mut MetricsState),
+    {
+        // Metrics are best effort, a panic while holding the lock must not stop
+        // them from being updated.
+        let mut state = self
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        update(&mut state);
+    }
+}
+
+fn write_metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: &[(&str, f64)],
+) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{name} {value}");
+        } else {
+            let _ = writeln!(out, "{name}{{{labels}}} {value}");
+        }
+    }
+}
+
+/// Starts serving the metrics at `/metrics` in a background thread.
+pub(crate) fn serve(config: &MetricsConfig) -> Result<()> {
+    let listener = TcpListener::bind(&config.listen_address)?;
+    info!(
+        "Serving metrics at http://{}/metrics",
+        listener.local_addr()?
+    );
+    thread::Builder::new()
+        .name("metrics".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.map_err(anyhow::Error::from).and_then(handle_request);
+                if let Err(e) = result {
+                    warn!("Failed to serve metrics request: {e}");
+                }
+            }
+        })?;
+    Ok(())
+}
+
+fn handle_request(mut stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers, they are irrelevant for serving the metrics
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+        header.clear();
+    }
+    debug!("Metrics request: {}", request_line.trim_end());
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = METRICS.render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: \
+                 {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+    stream.write_all(response.as_bytes())?;
+    Ok(())
+}
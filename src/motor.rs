@@ -1,31 +1,81 @@
-use std::sync::mpsc::Receiver;
-use std::sync::mpsc::TryRecvError;
+use std::panic;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::sync::TryLockError;
+use std::sync::Weak;
+use std::thread;
 use std::thread::sleep;
+use std::thread::JoinHandle;
 use std::time::Duration;
 use std::time::Instant;
+use std::time::SystemTime;
 
+use anyhow::anyhow;
+use anyhow::Result;
+use clap::ValueEnum;
 use log::debug;
+use log::info;
+use log::warn;
 use rppal::gpio::Gpio;
+use rppal::gpio::Level;
 use rppal::gpio::OutputPin;
+use serde::Deserialize;
 
+use crate::config::DutyCyclePolicy;
 use crate::config::MotorConfig;
+use crate::config::PinPolarity;
+use crate::config::PinRelease;
+use crate::config::RampConfig;
+use crate::duty_cycle::DutyCycle;
+use crate::metrics::METRICS;
+use crate::shutdown::Shutdown;
+use crate::systemd;
+
+// The number of steps to change the motor speed in when ramping it up or down
+const RAMP_STEPS: u32 = 20;
+// The heartbeat stops once the motor control loop has not made progress for
+// this long, on top of the time the motor takes to stop
+const HEARTBEAT_STALL_TIMEOUT: Duration = Duration::from_secs(2);
 
 /// A driver for handling the movement of the standing desk's motor.
 pub(crate) trait MotorDriver {
     /// Makes the motor move the table up until the provided condition is false
     /// or until the timeoout is reached.
+    ///
+    /// `expected_run_time` is the longest time the movement is expected to
+    /// take, e.g. estimated from the table's calibrated speed. If unknown, the
+    /// movement may take until the motor times out.
+    ///
+    /// # Errors
+    /// Errors if the motor is not allowed to run for the expected time, e.g.
+    /// because it needs to cool down.
     fn up_until_false_or_timeout<F>(
         &mut self,
+        expected_run_time: Option<Duration>,
         condition: &mut F,
-    ) where
+    ) -> Result<()>
+    where
         F: FnMut() -> bool;
 
-    /// Makes the motor move the table up until the provided condition is false
-    /// or until the timeoout is reached.
+    /// Makes the motor move the table down until the provided condition is
+    /// false or until the timeoout is reached.
+    ///
+    /// `expected_run_time` is the longest time the movement is expected to
+    /// take, e.g. estimated from the table's calibrated speed. If unknown, the
+    /// movement may take until the motor times out.
+    ///
+    /// # Errors
+    /// Errors if the motor is not allowed to run for the expected time, e.g.
+    /// because it needs to cool down.
     fn down_until_false_or_timeout<F>(
         &mut self,
+        expected_run_time: Option<Duration>,
         condition: &mut F,
-    ) where
+    ) -> Result<()>
+    where
         F: FnMut() -> bool;
 }
 
@@ -35,119 +85,548 @@ pub(crate) struct DeskMotorDriver {
     motor: DeskMotor,
     // The motor should not be (tried) to run for longer than this duration
     timeout: Duration,
-    // A receiver for an issued shutdown signal. We need this to gracefully stop the motor and drop
-    // reset the pins correctly.
-    shutdown_rx: Receiver<()>,
+    // An issued shutdown request. We need this to gracefully stop the motor and reset the pins
+    // correctly.
+    shutdown: Shutdown,
+    // Keeps the motor from running for too long in total, if configured
+    duty_cycle: Option<DutyCycle>,
 }
 
 impl DeskMotorDriver {
     /// Creates a new `DeskMotorDriver` with the provided configuration.
     ///
-    /// The `shutdown_rx` receiver is used for gracefully stopping the motor.
+    /// The `shutdown` request is used for gracefully stopping the motor, no
+    /// more movements are started once it has been requested or the movement
+    /// has been halted.
     ///
     /// # Panics
     /// Panics if the configured pins for driving the motor up or down are the
-    /// same or if they cannot be initialised.
+    /// same or if they cannot be initialised, or if the motor's duty cycle
+    /// state cannot be read.
     pub fn new(
-        config: MotorConfig,
-        shutdown_rx: Receiver<()>,
+        config: &MotorConfig,
+        shutdown: Shutdown,
     ) -> Self {
         Self {
             motor: DeskMotor::new(config),
             timeout: Duration::from_secs(config.timeout_secs),
-            shutdown_rx,
+            shutdown,
+            duty_cycle: config.duty_cycle.as_ref().map(DutyCycle::new),
         }
     }
 
     fn move_until_false_or_timeout<C>(
         &mut self,
         direction: MoveDirection,
+        expected_run_time: Option<Duration>,
         condition: &mut C,
-    ) where
+    ) -> Result<()>
+    where
         C: FnMut() -> bool,
     {
+        if self.shutdown.is_halted() {
+            return Err(anyhow!(
+                "not moving {direction:?} as the desk has been stopped"
+            ));
+        }
+        let expected_run_time =
+            expected_run_time.map_or(self.timeout, |run_time| run_time.min(self.timeout));
+        self.wait_for_cooldown(expected_run_time)?;
+        // Never run for longer than the duty cycle allows
+        let timeout = match &mut self.duty_cycle {
+            Some(duty_cycle) => {
+                let timeout = self.timeout.min(duty_cycle.run_budget());
+                duty_cycle.run_started(SystemTime::now(), timeout);
+                timeout
+            }
+            None => self.timeout,
+        };
         let now = Instant::now();
         match direction {
             MoveDirection::Up => self.motor.up(),
             MoveDirection::Down => self.motor.down(),
         }
-        while condition()
-            && now.elapsed() < self.timeout
-            && matches!(self.shutdown_rx.try_recv(), Err(TryRecvError::Empty))
-        {
+        while condition() && now.elapsed() < timeout && !self.shutdown.is_halted() {
+            self.motor.heartbeat();
+            systemd::ping_watchdog();
             sleep(Duration::from_millis(50));
         }
         self.motor.stop();
+        let run_time = now.elapsed();
+        METRICS.add_motor_run_time(direction, run_time);
+        if let Some(duty_cycle) = &mut self.duty_cycle {
+            duty_cycle.run_stopped(run_time);
+            // Only happens if the movement takes longer than expected or
+            // longer than the duty cycle allows at all
+            if run_time >= timeout && timeout < self.timeout {
+                warn!(
+                    "Stopped the motor early as its duty cycle limit of {} was reached",
+                    duty_cycle.describe_limit()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes sure the motor may run for `run_time` before starting a
+    /// movement, either by waiting for it to cool down or by refusing to move
+    /// depending on the configured policy.
+    fn wait_for_cooldown(
+        &self,
+        run_time: Duration,
+    ) -> Result<()> {
+        let Some(duty_cycle) = &self.duty_cycle else {
+            return Ok(());
+        };
+        let cooldown = duty_cycle.cooldown(run_time);
+        if cooldown.is_zero() {
+            return Ok(());
+        }
+        let message = format!(
+            "the movement may take up to {}s, which would exceed the motor's duty cycle limit of \
+             {}, and the motor needs to cool down for another {}",
+            run_time.as_secs_f32().ceil(),
+            duty_cycle.describe_limit(),
+            format_cooldown(cooldown)
+        );
+        if duty_cycle.policy() == DutyCyclePolicy::Refuse {
+            return Err(anyhow!(message));
+        }
+        info!("Waiting as {message}");
+        let start = Instant::now();
+        while start.elapsed() < cooldown {
+            if self.shutdown.is_halted() {
+                return Err(anyhow!(
+                    "interrupted while waiting for the motor to cool down"
+                ));
+            }
+            systemd::ping_watchdog();
+            sleep(Duration::from_millis(500));
+        }
+        Ok(())
     }
 }
 
 impl MotorDriver for DeskMotorDriver {
     fn up_until_false_or_timeout<C>(
         &mut self,
+        expected_run_time: Option<Duration>,
         condition: &mut C,
-    ) where
+    ) -> Result<()>
+    where
         C: FnMut() -> bool,
     {
-        self.move_until_false_or_timeout(MoveDirection::Up, condition);
+        self.move_until_false_or_timeout(MoveDirection::Up, expected_run_time, condition)
     }
 
     fn down_until_false_or_timeout<C>(
         &mut self,
+        expected_run_time: Option<Duration>,
         condition: &mut C,
-    ) where
+    ) -> Result<()>
+    where
         C: FnMut() -> bool,
     {
-        self.move_until_false_or_timeout(MoveDirection::Down, condition);
+        self.move_until_false_or_timeout(MoveDirection::Down, expected_run_time, condition)
     }
 }
 
-#[derive(Debug)]
+/// Formats a cooldown as minutes and seconds, e.g. `3m 05s`.
+fn format_cooldown(cooldown: Duration) -> String {
+    let secs = cooldown.as_secs();
+    format!("{}m {:02}s", secs / 60, secs % 60)
+}
 
-enum MoveDirection {
+/// The direction the table moves in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum MoveDirection {
     Up,
     Down,
 }
 
 #[derive(Debug)]
 struct DeskMotor {
-    pin_up: OutputPin,
-    pin_down: OutputPin,
+    // Shared with the panic hook, which stops the motor if anything panics
+    pins: Arc<Mutex<MotorPins>>,
+    // Toggled while the motor runs so that an external watchdog can cut the
+    // power if the program hangs, if configured
+    heartbeat: Option<Heartbeat>,
+    // The pause between stopping and moving in the opposite direction
+    dead_time: Duration,
+    // The shortest time the motor runs once started
+    min_pulse: Duration,
+    // Ramps the motor speed up and down if set
+    ramp: Option<RampConfig>,
+    // The direction and start time of the current run, if any
+    running: Option<(MoveDirection, Instant)>,
+    // The direction and end time of the last run, if any
+    last_run: Option<(MoveDirection, Instant)>,
+}
+
+#[derive(Debug)]
+struct MotorPins {
+    up: MotorPin,
+    down: MotorPin,
 }
 
 impl DeskMotor {
-    /// Creates a new `DeskMotor`.
+    /// Creates a new `DeskMotor`, with the motor idle from the moment the pins
+    /// are claimed.
+    ///
+    /// A panic hook is installed which stops the motor before any other panic
+    /// handling.
     ///
     /// # Panics
     /// Panics if the configured pins for driving the motor up or down are the
     /// same or if they cannot be initialised.
-    fn new(config: MotorConfig) -> Self {
+    fn new(config: &MotorConfig) -> Self {
         let gpio = Gpio::new().expect("gpio to be available");
-        let pin_up = gpio
-            .get(config.up_pin)
-            .expect("pin up to be available")
-            .into_output();
-        let pin_down = gpio
-            .get(config.down_pin)
-            .expect("pin down to be available")
-            .into_output();
-        Self { pin_up, pin_down }
+        let up = MotorPin::new(
+            &gpio,
+            config.up_pin,
+            config.up_pin_polarity,
+            config.up_pin_release,
+        )
+        .expect("pin up to be available");
+        let down = MotorPin::new(
+            &gpio,
+            config.down_pin,
+            config.down_pin_polarity,
+            config.down_pin_release,
+        )
+        .expect("pin down to be available");
+        // Stopping the motor ramps it down after its minimum pulse length
+        // without the control loop making progress
+        let stop_time = Duration::from_millis(config.min_pulse_ms)
+            + config.ramp.map_or(Duration::ZERO, |ramp| {
+                Duration::from_millis(ramp.ramp_up_ms.max(ramp.ramp_down_ms))
+            });
+        let heartbeat = config.heartbeat.map(|heartbeat_config| {
+            Heartbeat::new(
+                gpio.get(heartbeat_config.pin)
+                    .expect("heartbeat pin to be available")
+                    .into_output_low(),
+                Duration::from_millis(heartbeat_config.interval_ms),
+                HEARTBEAT_STALL_TIMEOUT + stop_time,
+            )
+        });
+        let pins = Arc::new(Mutex::new(MotorPins { up, down }));
+        install_panic_hook(Arc::downgrade(&pins));
+        Self {
+            pins,
+            heartbeat,
+            dead_time: Duration::from_millis(config.dead_time_ms),
+            min_pulse: Duration::from_millis(config.min_pulse_ms),
+            ramp: config.ramp,
+            running: None,
+            last_run: None,
+        }
     }
 
     fn up(&mut self) {
-        self.stop();
-        debug!("Moving up");
-        self.pin_up.set_high();
+        self.start(MoveDirection::Up);
     }
 
     fn down(&mut self) {
+        self.start(MoveDirection::Down);
+    }
+
+    fn start(
+        &mut self,
+        direction: MoveDirection,
+    ) {
         self.stop();
-        debug!("Moving down");
-        self.pin_down.set_high();
+        // Give the relays and the motor some time before reversing
+        if let Some((last_direction, stopped_at)) = self.last_run {
+            if last_direction != direction {
+                sleep(self.dead_time.saturating_sub(stopped_at.elapsed()));
+            }
+        }
+        // Make sure the watchdog powers the motor before it starts
+        if let Some(heartbeat) = &mut self.heartbeat {
+            heartbeat.start();
+        }
+        debug!("Moving {direction:?}");
+        let ramp = self.ramp;
+        let mut pins = self.lock_pins();
+        let pin = pins.pin(direction);
+        match ramp {
+            Some(ramp) => pin.ramp(&ramp, RampDirection::Up),
+            None => pin.activate(),
+        }
+        drop(pins);
+        self.running = Some((direction, Instant::now()));
     }
 
     fn stop(&mut self) {
         debug!("Stopping");
-        self.pin_up.set_low();
-        self.pin_down.set_low();
+        self.heartbeat();
+        if let Some((direction, started_at)) = self.running.take() {
+            sleep(self.min_pulse.saturating_sub(started_at.elapsed()));
+            if let Some(ramp) = self.ramp {
+                self.lock_pins()
+                    .pin(direction)
+                    .ramp(&ramp, RampDirection::Down);
+            }
+            self.last_run = Some((direction, Instant::now()));
+        }
+        self.lock_pins().stop();
+        if let Some(heartbeat) = &mut self.heartbeat {
+            heartbeat.stop();
+        }
+    }
+
+    /// Tells the heartbeat that the motor control loop is still making
+    /// progress, which must be done regularly while the motor runs.
+    fn heartbeat(&self) {
+        if let Some(heartbeat) = &self.heartbeat {
+            heartbeat.feed();
+        }
+    }
+
+    fn lock_pins(&self) -> MutexGuard<'_, MotorPins> {
+        // The pins are always left in a consistent state, so a panic while
+        // holding the lock does not matter
+        self.pins
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Drop for DeskMotor {
+    fn drop(&mut self) {
+        // Stop right away, without waiting for the minimum pulse length or
+        // ramping down
+        self.lock_pins().stop();
+    }
+}
+
+impl MotorPins {
+    fn pin(
+        &mut self,
+        direction: MoveDirection,
+    ) -> &mut MotorPin {
+        match direction {
+            MoveDirection::Up => &mut self.up,
+            MoveDirection::Down => &mut self.down,
+        }
+    }
+
+    fn stop(&mut self) {
+        self.up.deactivate();
+        self.down.deactivate();
     }
 }
+
+/// Installs a panic hook which stops the motor before running the previous
+/// hook, e.g. for panics in other threads or if unwinding is disabled.
+fn install_panic_hook(pins: Weak<Mutex<MotorPins>>) {
+    let previous_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if let Some(pins) = pins.upgrade() {
+            // The panicking thread may be holding the lock itself, in which case
+            // the pins are stopped when it unwinds and drops the motor
+            let pins = match pins.try_lock() {
+                Ok(pins) => Some(pins),
+                Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+                Err(TryLockError::WouldBlock) => None,
+            };
+            if let Some(mut pins) = pins {
+                pins.stop();
+            }
+        }
+        previous_hook(info);
+    }));
+}
+
+/// A pin toggled regularly from a separate thread while the motor runs, as
+/// long as the motor control loop keeps making progress.
+#[derive(Debug)]
+struct Heartbeat {
+    // Owned by the toggling thread while it runs
+    pin: Option<OutputPin>,
+    interval: Duration,
+    // Toggling stops once the control loop has not made progress for this long
+    stall_timeout: Duration,
+    // When the control loop last made progress, shared with the toggling thread
+    fed_at: Arc<Mutex<Instant>>,
+    // Tells the toggling thread to stop and hand back the pin
+    stopping: Arc<AtomicBool>,
+    thread: Option<JoinHandle<OutputPin>>,
+}
+
+impl Heartbeat {
+    fn new(
+        pin: OutputPin,
+        interval: Duration,
+        stall_timeout: Duration,
+    ) -> Self {
+        Self {
+            pin: Some(pin),
+            interval,
+            stall_timeout,
+            fed_at: Arc::new(Mutex::new(Instant::now())),
+            stopping: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+
+    /// Toggles the pin right away and keeps toggling it in the background
+    /// until stopped or until the control loop stalls.
+    ///
+    /// # Panics
+    /// Panics if the toggling thread cannot be started.
+    fn start(&mut self) {
+        let Some(mut pin) = self.pin.take() else {
+            // Already running
+            return;
+        };
+        self.feed();
+        self.stopping.store(false, Ordering::SeqCst);
+        pin.toggle();
+        let interval = self.interval;
+        let stall_timeout = self.stall_timeout;
+        let fed_at = Arc::clone(&self.fed_at);
+        let stopping = Arc::clone(&self.stopping);
+        let thread = thread::Builder::new()
+            .name("heartbeat".to_string())
+            .spawn(move || {
+                let mut stalled = false;
+                loop {
+                    thread::park_timeout(interval);
+                    if stopping.load(Ordering::SeqCst) {
+                        return pin;
+                    }
+                    let fed_at = *fed_at.lock().unwrap_or_else(|e| e.into_inner());
+                    if fed_at.elapsed() < stall_timeout {
+                        pin.toggle();
+                    } else if !stalled {
+                        warn!("The motor control loop stalled, stopping the heartbeat");
+                        stalled = true;
+                    }
+                }
+            })
+            .expect("be able to start the heartbeat thread");
+        self.thread = Some(thread);
+    }
+
+    /// Records that the control loop is still making progress.
+    fn feed(&self) {
+        *self.fed_at.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+    }
+
+    /// Stops toggling the pin.
+    fn stop(&mut self) {
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        self.stopping.store(true, Ordering::SeqCst);
+        thread.thread().unpark();
+        match thread.join() {
+            Ok(pin) => self.pin = Some(pin),
+            Err(_) => warn!("The heartbeat thread panicked"),
+        }
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// An output pin driving the motor in one direction, taking the polarity of
+/// the connected relay board or motor controller into account.
+///
+/// The pin is idle when claimed and when released.
+#[derive(Debug)]
+struct MotorPin {
+    pin: OutputPin,
+    // The level which makes the motor move
+    active_level: Level,
+}
+
+impl MotorPin {
+    fn new(
+        gpio: &Gpio,
+        pin_number: u8,
+        polarity: PinPolarity,
+        release: PinRelease,
+    ) -> Result<Self> {
+        let pin = gpio.get(pin_number)?;
+        // Configure the pin as output at its idle level right away, switching
+        // modes first could make an active-low relay board move the table
+        let (mut pin, active_level) = match polarity {
+            PinPolarity::ActiveHigh => (pin.into_output_low(), Level::High),
+            PinPolarity::ActiveLow => (pin.into_output_high(), Level::Low),
+        };
+        pin.set_reset_on_drop(release == PinRelease::Reset);
+        Ok(Self { pin, active_level })
+    }
+
+    fn activate(&mut self) {
+        self.pin.write(self.active_level);
+    }
+
+    fn deactivate(&mut self) {
+        self.pin.write(!self.active_level);
+    }
+
+    /// Gradually changes the duty cycle of the software PWM between off and
+    /// full speed, leaving the pin active after ramping up and idle after
+    /// ramping down.
+    ///
+    /// Falls back to switching the pin directly if PWM is unavailable.
+    fn ramp(
+        &mut self,
+        ramp: &RampConfig,
+        direction: RampDirection,
+    ) {
+        let duration = match direction {
+            RampDirection::Up => Duration::from_millis(ramp.ramp_up_ms),
+            RampDirection::Down => Duration::from_millis(ramp.ramp_down_ms),
+        };
+        for step in 1..RAMP_STEPS {
+            let speed = step as f64 / RAMP_STEPS as f64;
+            let speed = match direction {
+                RampDirection::Up => speed,
+                RampDirection::Down => 1.0 - speed,
+            };
+            // The duty cycle is the share of time the pin is high
+            let duty_cycle = match self.active_level {
+                Level::High => speed,
+                Level::Low => 1.0 - speed,
+            };
+            if let Err(e) = self
+                .pin
+                .set_pwm_frequency(ramp.pwm_frequency_hz, duty_cycle)
+            {
+                warn!("Cannot ramp the motor speed: {e}");
+                break;
+            }
+            sleep(duration / RAMP_STEPS);
+        }
+        if let Err(e) = self.pin.clear_pwm() {
+            warn!("Cannot stop ramping the motor speed: {e}");
+        }
+        match direction {
+            RampDirection::Up => self.activate(),
+            RampDirection::Down => self.deactivate(),
+        }
+    }
+}
+
+impl Drop for MotorPin {
+    fn drop(&mut self) {
+        // Leave the pin idle, even if it is reset to an input afterwards
+        let _ = self.pin.clear_pwm();
+        self.deactivate();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RampDirection {
+    Up,
+    Down,
+}
@@ -1,5 +1,6 @@
 use anyhow::Result;
 
+use crate::motor::MoveDirection;
 use crate::primitives::Centimeter;
 
 /// A trait for making movements.
@@ -11,11 +12,44 @@ pub(crate) trait Movement {
     fn move_to_sitting(&mut self) -> Result<()>;
 
     /// Calibrates movements so that moving to a specific height is accurate.
-    fn calibrate(&mut self) -> Result<()>;
+    ///
+    /// The ambient temperature is recorded with the calibration if known.
+    fn calibrate(
+        &mut self,
+        temperature_celsius: Option<f32>,
+    ) -> Result<()>;
 
     /// Moves to a specific height in centimeters.
     fn move_to_height(
         &mut self,
         height_cm: Centimeter,
     ) -> Result<()>;
+
+    /// Moves by a distance in centimeters relative to the current height,
+    /// stopping at the table's height limits.
+    fn move_by(
+        &mut self,
+        direction: MoveDirection,
+        distance_cm: Centimeter,
+    ) -> Result<()>;
+
+    /// Moves in the given direction while the condition holds, stopping at
+    /// the table's height limits.
+    fn jog<F>(
+        &mut self,
+        direction: MoveDirection,
+        condition: F,
+    ) -> Result<()>
+    where
+        F: FnMut() -> bool;
+
+    /// Stops all following movements, including calibrations, as soon as
+    /// `abort` returns true while the motor runs, e.g. on a key press.
+    fn abort_when(
+        &mut self,
+        abort: fn() -> bool,
+    );
+
+    /// Measures the current height in centimeters.
+    fn current_height(&mut self) -> Result<Centimeter>;
 }
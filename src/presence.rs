@@ -0,0 +1,181 @@
+use std::fmt::Debug;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread::sleep;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use log::debug;
+use log::warn;
+use rppal::gpio::Gpio;
+use rppal::gpio::InputPin;
+
+use crate::calibration::echo_secs_to_cm;
+use crate::config::PresenceConfig;
+use crate::config::PresenceSourceConfig;
+use crate::sensor::DistanceSensor;
+use crate::sensor::HCSR04;
+
+// How often the PIR sensor is read while sampling it
+const PIR_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A way of telling whether somebody is at the desk.
+pub(crate) trait PresenceSource: Debug {
+    /// Whether somebody is at the desk.
+    fn is_present(&mut self) -> Result<bool>;
+}
+
+/// Decides whether somebody is at the desk from the configured sources, so
+/// that automated movements can be skipped while nobody is there.
+#[derive(Debug)]
+pub(crate) struct Presence {
+    sources: Vec<Box<dyn PresenceSource>>,
+    // Whether to assume somebody is present if a source fails
+    present_on_error: bool,
+}
+
+impl Presence {
+    /// Creates a new [Presence] with the configured sources.
+    ///
+    /// # Panics
+    /// Panics if the pins of a source cannot be initialised.
+    pub(crate) fn new(config: &PresenceConfig) -> Self {
+        let sources = config
+            .sources
+            .iter()
+            .map(|source_config| -> Box<dyn PresenceSource> {
+                match source_config {
+                    PresenceSourceConfig::Pir { pin, sample_secs } => Box::new(PirSensor {
+                        pin: Gpio::new()
+                            .expect("gpio to be available")
+                            .get(*pin)
+                            .expect("PIR sensor pin to be available")
+                            .into_input_pulldown(),
+                        sample_duration: Duration::from_secs(*sample_secs),
+                    }),
+                    PresenceSourceConfig::Ultrasonic {
+                        trigger_pin,
+                        echo_pin,
+                        max_distance_cm,
+                    } => Box::new(ChairSensor {
+                        sensor: HCSR04::uncalibrated(*trigger_pin, *echo_pin),
+                        max_distance_cm: *max_distance_cm,
+                    }),
+                    PresenceSourceConfig::File { path, max_age_mins } => Box::new(ActivityFile {
+                        path: path.clone(),
+                        max_age: Duration::from_secs(max_age_mins * 60),
+                    }),
+                    PresenceSourceConfig::Command { command } => Box::new(PresenceCommand {
+                        command: command.clone(),
+                    }),
+                }
+            })
+            .collect();
+        Self {
+            sources,
+            present_on_error: config.present_on_error,
+        }
+    }
+
+    /// Whether any source detects somebody at the desk. Without any sources,
+    /// somebody is always assumed to be present.
+    pub(crate) fn is_present(&mut self) -> bool {
+        if self.sources.is_empty() {
+            return true;
+        }
+        for source in &mut self.sources {
+            match source.is_present() {
+                Ok(true) => {
+                    debug!("Presence detected by {source:?}");
+                    return true;
+                }
+                Ok(false) => debug!("No presence detected by {source:?}"),
+                Err(e) => {
+                    warn!("Could not detect presence with {source:?}: {e:#}");
+                    if self.present_on_error {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+}
+
+/// A passive infrared motion sensor whose output is high while it detects
+/// motion.
+#[derive(Debug)]
+struct PirSensor {
+    pin: InputPin,
+    // How long to watch for motion
+    sample_duration: Duration,
+}
+
+impl PresenceSource for PirSensor {
+    fn is_present(&mut self) -> Result<bool> {
+        let start = Instant::now();
+        while start.elapsed() < self.sample_duration {
+            if self.pin.is_high() {
+                return Ok(true);
+            }
+            sleep(PIR_POLL_INTERVAL);
+        }
+        Ok(self.pin.is_high())
+    }
+}
+
+/// An ultrasonic distance sensor pointed at the chair, which is occupied if
+/// something is closer than the configured distance.
+#[derive(Debug)]
+struct ChairSensor {
+    sensor: HCSR04,
+    max_distance_cm: f32,
+}
+
+impl PresenceSource for ChairSensor {
+    fn is_present(&mut self) -> Result<bool> {
+        let distance_cm = echo_secs_to_cm(self.sensor.echo_duration()?.as_secs_f32(), None);
+        debug!("Distance to the chair: {distance_cm:.1}cm");
+        Ok(distance_cm <= self.max_distance_cm)
+    }
+}
+
+/// A file touched on activity, e.g. by a screen saver or idle hook, which
+/// indicates presence if it has been modified recently.
+#[derive(Debug)]
+struct ActivityFile {
+    path: PathBuf,
+    max_age: Duration,
+}
+
+impl PresenceSource for ActivityFile {
+    fn is_present(&mut self) -> Result<bool> {
+        let modified = fs::metadata(&self.path)?.modified()?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        Ok(age <= self.max_age)
+    }
+}
+
+/// A shell command which exits successfully if somebody is present, e.g. a
+/// check of the session's idle hint.
+#[derive(Debug)]
+struct PresenceCommand {
+    command: String,
+}
+
+impl PresenceSource for PresenceCommand {
+    fn is_present(&mut self) -> Result<bool> {
+        let status = Command::new("sh").arg("-c").arg(&self.command).status()?;
+        match status.code() {
+            Some(0) => Ok(true),
+            Some(_) => Ok(false),
+            None => Err(anyhow!("the presence command was terminated by a signal")),
+        }
+    }
+}
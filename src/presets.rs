@@ -0,0 +1,30 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::primitives::Centimeter;
+use crate::storage::write_atomically;
+
+/// Loads the presets saved at runtime, e.g. from the terminal UI, of which
+/// there are none if none have been saved yet.
+pub(crate) fn load(path: &Path) -> Result<BTreeMap<String, Centimeter>> {
+    match fs::read_to_string(path) {
+        Ok(raw_presets) => Ok(toml::from_str(&raw_presets)?),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Saves a preset, replacing any saved preset with the same name.
+pub(crate) fn save(
+    path: &Path,
+    name: &str,
+    height: Centimeter,
+) -> Result<()> {
+    let mut presets = load(path)?;
+    presets.insert(name.to_string(), height);
+    write_atomically(path, toml::to_string(&presets)?.as_bytes())
+}
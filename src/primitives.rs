@@ -6,7 +6,7 @@ use serde::Serialize;
 
 /// A reasonable unit for table height.
 /// The table cannot be higher than 255cm
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Deserialize, Serialize)]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Deserialize, Serialize)]
 pub(crate) struct Centimeter(pub u8);
 
 impl Centimeter {
@@ -1,13 +1,17 @@
+use std::fmt::Debug;
 use std::fs;
+use std::io::ErrorKind;
 use std::path::Path;
 use std::path::PathBuf;
 use std::thread::sleep;
 use std::time::Duration;
+use std::time::Instant;
 use std::time::SystemTime;
 
 use anyhow::anyhow;
 use anyhow::Result;
 use log::debug;
+use log::warn;
 use rppal::gpio::Gpio;
 use rppal::gpio::InputPin;
 use rppal::gpio::Level;
@@ -16,11 +20,19 @@ use rppal::gpio::Trigger;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::calibration::echo_secs_to_cm;
+use crate::calibration::CalibrationMetadata;
+use crate::calibration::MotionCalibration;
 use crate::config::SensorConfig;
+use crate::metrics::METRICS;
+use crate::motor::MoveDirection;
 use crate::primitives::Centimeter;
 
+// The highest standard deviation of the measurements for a calibrated height
+const MAX_CALIBRATION_STD_DEV_CM: f32 = 1.0;
+
 /// The abstraction of a distance sensor.
-pub(crate) trait DistanceSensor {
+pub(crate) trait DistanceSensor: Debug {
     /// Takes a height measurement in centimeters.
     fn current_height(&mut self) -> Result<Centimeter>;
 
@@ -36,16 +48,147 @@ pub(crate) trait DistanceSensor {
         height: Centimeter,
     ) -> Result<()>;
 
+    /// Measures the raw duration of an echo without applying any calibration.
+    fn echo_duration(&mut self) -> Result<Duration>;
+
+    /// The number of samples taken for calibrating a single height.
+    fn calibration_samples(&self) -> u8;
+
+    /// Replaces the calibration data, e.g. for restoring the previous data
+    /// after a failed calibration.
+    fn restore_calibration_data(
+        &mut self,
+        calibration_data: Option<SensorCalibrationData>,
+    );
+
+    /// The path of the file the calibration data is persisted in.
     fn calibration_file(&self) -> &Path;
 
-    fn calibration_data(&self) -> &SensorCalibrationData;
+    /// The calibration data, if the sensor has been calibrated yet.
+    fn calibration_data(&self) -> Option<&SensorCalibrationData>;
+
+    /// The mutable calibration data, if the sensor has been calibrated yet.
+    fn calibration_data_mut(&mut self) -> Option<&mut SensorCalibrationData>;
+
+    /// Whether the height is actually measured, as opposed to being estimated
+    /// from the table's movements.
+    fn measures_height(&self) -> bool {
+        true
+    }
+
+    /// Whether the height is uncertain so that the table needs to be moved to
+    /// its lowest position before it can be moved to a specific height.
+    fn needs_homing(&self) -> bool {
+        false
+    }
+
+    /// Notifies the sensor that the motor started moving the table.
+    fn movement_started(
+        &mut self,
+        _direction: MoveDirection,
+    ) {
+    }
+
+    /// Notifies the sensor that the motor stopped moving the table.
+    fn movement_stopped(&mut self) {}
+
+    /// Notifies the sensor that the table was moved to an end stop at the
+    /// given height.
+    fn end_stop_reached(
+        &mut self,
+        _height: Centimeter,
+    ) {
+    }
+
+    /// Notifies the sensor that moving to an end stop was cut short, e.g.
+    /// because the movement was halted, so that the table's position is
+    /// unknown.
+    fn end_stop_missed(&mut self) {}
+}
+
+impl<S: DistanceSensor + ?Sized> DistanceSensor for Box<S> {
+    fn current_height(&mut self) -> Result<Centimeter> {
+        (**self).current_height()
+    }
+
+    fn set_min_height(
+        &mut self,
+        height: Centimeter,
+    ) -> Result<()> {
+        (**self).set_min_height(height)
+    }
+
+    fn set_max_height(
+        &mut self,
+        height: Centimeter,
+    ) -> Result<()> {
+        (**self).set_max_height(height)
+    }
+
+    fn echo_duration(&mut self) -> Result<Duration> {
+        (**self).echo_duration()
+    }
+
+    fn calibration_samples(&self) -> u8 {
+        (**self).calibration_samples()
+    }
+
+    fn restore_calibration_data(
+        &mut self,
+        calibration_data: Option<SensorCalibrationData>,
+    ) {
+        (**self).restore_calibration_data(calibration_data)
+    }
+
+    fn calibration_file(&self) -> &Path {
+        (**self).calibration_file()
+    }
+
+    fn calibration_data(&self) -> Option<&SensorCalibrationData> {
+        (**self).calibration_data()
+    }
+
+    fn calibration_data_mut(&mut self) -> Option<&mut SensorCalibrationData> {
+        (**self).calibration_data_mut()
+    }
+
+    fn measures_height(&self) -> bool {
+        (**self).measures_height()
+    }
+
+    fn needs_homing(&self) -> bool {
+        (**self).needs_homing()
+    }
+
+    fn movement_started(
+        &mut self,
+        direction: MoveDirection,
+    ) {
+        (**self).movement_started(direction)
+    }
+
+    fn movement_stopped(&mut self) {
+        (**self).movement_stopped()
+    }
+
+    fn end_stop_reached(
+        &mut self,
+        height: Centimeter,
+    ) {
+        (**self).end_stop_reached(height)
+    }
+
+    fn end_stop_missed(&mut self) {
+        (**self).end_stop_missed()
+    }
 }
 
 /// The HCSR04 sensor for measuring distances.
 #[derive(Debug)]
 pub(crate) struct HCSR04 {
     calibration_file_path: PathBuf,
-    calibration_data: SensorCalibrationData,
+    // No calibration data is available before the first calibration
+    calibration_data: Option<SensorCalibrationData>,
     trigger_pin: OutputPin,
     echo_pin: InputPin,
     // We take several measurements and average them to get a less noisy estimate
@@ -54,10 +197,12 @@ pub(crate) struct HCSR04 {
     // This is a u8 because it doesn't make sense to take more than 256 measurements in burst
     // as that would equal to around 7s of measurement time.
     measurement_burst: u8,
+    // The number of measurements to take when calibrating a height.
+    calibration_samples: u8,
 }
 
 /// A struct for storing the calibration data for the sensor.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
 pub(crate) struct SensorCalibrationData {
     // The minimum height that can be observed
     pub min_height: Centimeter,
@@ -67,27 +212,75 @@ pub(crate) struct SensorCalibrationData {
     pub max_height: Centimeter,
     // The duration of the echo in seconds at max height
     pub max_height_echo_secs: f32,
+    // Information about the calibration, missing for calibrations done before
+    // it was recorded
+    #[serde(default)]
+    pub metadata: Option<CalibrationMetadata>,
+    // How the table moves between its lowest and highest positions
+    #[serde(default)]
+    pub motion: Option<MotionCalibration>,
 }
 
 impl SensorCalibrationData {
     /// Loads calibration data from a file.
-    pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let raw_data = fs::read_to_string(path)?;
+    ///
+    /// Returns `None` if the file does not exist, e.g. because the sensor has
+    /// not been calibrated yet.
+    pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>> {
+        let raw_data = match fs::read_to_string(path) {
+            Ok(raw_data) => raw_data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
         let calibration = toml::from_str(&raw_data)?;
-        Ok(calibration)
+        Ok(Some(calibration))
     }
 }
 
 impl HCSR04 {
     /// Creates a new [HCSR04] instance with calibration parameters from the
     /// file.
+    ///
+    /// A missing calibration file is not an error, the sensor then needs to be
+    /// calibrated before taking height measurements.
     pub(crate) fn new(config: SensorConfig) -> Self {
-        let gpio = Gpio::new().expect("gpio to be available");
         let calibration_file_path = config.calibration_file;
         let calibration_data = SensorCalibrationData::load(&calibration_file_path)
-            .expect("calibration data must be available");
+            .expect("calibration data must be readable");
+        match calibration_data
+            .as_ref()
+            .map(|calibration_data| &calibration_data.metadata)
+        {
+            None => warn!("No calibration data found at {calibration_file_path:?}"),
+            Some(Some(metadata)) => METRICS.set_calibrated_at(metadata.calibrated_at),
+            Some(None) => (),
+        }
+        Self::with_pins(
+            config.trigger_pin.expect("trigger pin to be configured"),
+            config.echo_pin.expect("echo pin to be configured"),
+            calibration_file_path,
+            calibration_data,
+        )
+    }
+
+    /// Creates a new [HCSR04] instance without calibration, e.g. for measuring
+    /// raw distances with [`DistanceSensor::echo_duration`].
+    pub(crate) fn uncalibrated(
+        trigger_pin: u8,
+        echo_pin: u8,
+    ) -> Self {
+        Self::with_pins(trigger_pin, echo_pin, PathBuf::new(), None)
+    }
+
+    fn with_pins(
+        trigger_pin: u8,
+        echo_pin: u8,
+        calibration_file_path: PathBuf,
+        calibration_data: Option<SensorCalibrationData>,
+    ) -> Self {
+        let gpio = Gpio::new().expect("gpio to be available");
         let mut echo_pin = gpio
-            .get(config.echo_pin)
+            .get(echo_pin)
             .expect("echo pin be available")
             // Echo should be on low per default
             .into_input_pulldown();
@@ -97,32 +290,74 @@ impl HCSR04 {
             .set_interrupt(Trigger::Both)
             .expect("must be able to set echo interrupt trigger");
         let measurement_burst = 3;
+        let calibration_samples = 10;
         Self {
             calibration_file_path,
             calibration_data,
             trigger_pin: gpio
-                .get(config.trigger_pin)
+                .get(trigger_pin)
                 .expect("trigger pin be available")
                 .into_output(),
             echo_pin,
-            measurement_buffer: Vec::with_capacity(measurement_burst as usize),
+            measurement_buffer: Vec::with_capacity(calibration_samples as usize),
             measurement_burst,
+            calibration_samples,
         }
     }
 
     /// Performs multiple echo measurements and takes the average for a less
     /// noisy signal.
     fn measure_burst_echo_duration(&mut self) -> Result<Duration> {
+        self.fill_measurement_buffer(self.measurement_burst)?;
+        let average_burst_echo_duration = self.average_buffered_echo_duration();
+        debug!("average_burst_echo_duration: {average_burst_echo_duration:?}");
+        Ok(average_burst_echo_duration)
+    }
+
+    /// Performs many echo measurements for calibration and takes the average.
+    ///
+    /// # Errors
+    /// Errors if the measurements vary too much to be a reliable reference.
+    fn measure_calibration_echo_duration(&mut self) -> Result<Duration> {
+        self.fill_measurement_buffer(self.calibration_samples)?;
+        let average_echo_duration = self.average_buffered_echo_duration();
+        let average_echo_secs = average_echo_duration.as_secs_f32();
+        let variance = self
+            .measurement_buffer
+            .iter()
+            .map(|echo| (echo.as_secs_f32() - average_echo_secs).powi(2))
+            .sum::<f32>()
+            / self.measurement_buffer.len() as f32;
+        let std_dev_cm = echo_secs_to_cm(variance.sqrt(), None);
+        debug!("Calibration echo duration: {average_echo_duration:?}, std dev: {std_dev_cm:.2}cm");
+        if std_dev_cm > MAX_CALIBRATION_STD_DEV_CM {
+            return Err(anyhow!(
+                "calibration measurements are too noisy (standard deviation of \
+                 {std_dev_cm:.2}cm, at most {MAX_CALIBRATION_STD_DEV_CM}cm allowed), make sure \
+                 nothing moves in front of the sensor"
+            ));
+        }
+        Ok(average_echo_duration)
+    }
+
+    fn fill_measurement_buffer(
+        &mut self,
+        samples: u8,
+    ) -> Result<()> {
         self.measurement_buffer.clear();
-        for _ in 0..self.measurement_burst {
-            let echo = self.measure_one_full_echo_duration()?;
+        for _ in 0..samples {
+            let measurement_start = Instant::now();
+            let echo = self.measure_one_full_echo_duration();
+            METRICS.observe_measurement(measurement_start.elapsed(), echo.is_err());
+            let echo = echo?;
             self.measurement_buffer.push(echo);
             sleep(Duration::from_millis(30));
         }
-        let average_burst_echo_duration =
-            self.measurement_buffer.iter().sum::<Duration>() / self.measurement_burst as u32;
-        debug!("average_burst_echo_duration: {average_burst_echo_duration:?}");
-        Ok(average_burst_echo_duration)
+        Ok(())
+    }
+
+    fn average_buffered_echo_duration(&self) -> Duration {
+        self.measurement_buffer.iter().sum::<Duration>() / self.measurement_buffer.len() as u32
     }
 
     /// Measures the time it takes for the sensor to send and receive an
@@ -182,18 +417,24 @@ impl DistanceSensor for HCSR04 {
     /// Computes the sensor's current height, taking the calibration data into
     /// account.
     fn current_height(&mut self) -> Result<Centimeter> {
-        let echo_duration = self.measure_burst_echo_duration()?.as_secs_f32();
+        let Some(calibration_data) = &self.calibration_data else {
+            return Err(anyhow!(
+                "sensor is not calibrated yet, please run `standup calibrate` first"
+            ));
+        };
+        let min_height = calibration_data.min_height;
+        let max_height = calibration_data.max_height;
         // We're interpolating the height from our calibration parameters
-        let min_height_calibration_echo = self.calibration_data.min_height_echo_secs;
-        let max_height_calibration_echo = self.calibration_data.max_height_echo_secs;
+        let min_height_calibration_echo = calibration_data.min_height_echo_secs;
+        let max_height_calibration_echo = calibration_data.max_height_echo_secs;
+        let echo_duration = self.measure_burst_echo_duration()?.as_secs_f32();
         let normalized_echo = (echo_duration - min_height_calibration_echo)
             / (max_height_calibration_echo - min_height_calibration_echo);
-        let height = normalized_echo
-            * (self.calibration_data.max_height - self.calibration_data.min_height).into_inner()
-                as f32
-            + self.calibration_data.min_height.into_inner() as f32;
+        let height = normalized_echo * (max_height - min_height).into_inner() as f32
+            + min_height.into_inner() as f32;
         let height = Centimeter(height.round() as u8);
         debug!("Current height is {height:?}");
+        METRICS.set_current_height(height);
         Ok(height)
     }
 
@@ -203,10 +444,11 @@ impl DistanceSensor for HCSR04 {
         height: Centimeter,
     ) -> Result<()> {
         debug!("Setting min height {height:?}");
-        let echo_duration = self.measure_burst_echo_duration()?;
+        let echo_duration = self.measure_calibration_echo_duration()?;
         debug!("Min height echo duration: {echo_duration:?}");
-        self.calibration_data.min_height_echo_secs = echo_duration.as_secs_f32();
-        self.calibration_data.min_height = height;
+        let calibration_data = self.calibration_data.get_or_insert_with(Default::default);
+        calibration_data.min_height_echo_secs = echo_duration.as_secs_f32();
+        calibration_data.min_height = height;
         Ok(())
     }
 
@@ -216,18 +458,38 @@ impl DistanceSensor for HCSR04 {
         height: Centimeter,
     ) -> Result<()> {
         debug!("Setting max height {height:?}");
-        let echo_duration = self.measure_burst_echo_duration()?;
+        let echo_duration = self.measure_calibration_echo_duration()?;
         debug!("Max height echo duration: {echo_duration:?}");
-        self.calibration_data.max_height_echo_secs = echo_duration.as_secs_f32();
-        self.calibration_data.max_height = height;
+        let calibration_data = self.calibration_data.get_or_insert_with(Default::default);
+        calibration_data.max_height_echo_secs = echo_duration.as_secs_f32();
+        calibration_data.max_height = height;
         Ok(())
     }
 
+    fn echo_duration(&mut self) -> Result<Duration> {
+        self.measure_burst_echo_duration()
+    }
+
+    fn calibration_samples(&self) -> u8 {
+        self.calibration_samples
+    }
+
+    fn restore_calibration_data(
+        &mut self,
+        calibration_data: Option<SensorCalibrationData>,
+    ) {
+        self.calibration_data = calibration_data;
+    }
+
     fn calibration_file(&self) -> &Path {
         &self.calibration_file_path
     }
 
-    fn calibration_data(&self) -> &SensorCalibrationData {
-        &self.calibration_data
+    fn calibration_data(&self) -> Option<&SensorCalibrationData> {
+        self.calibration_data.as_ref()
+    }
+
+    fn calibration_data_mut(&mut self) -> Option<&mut SensorCalibrationData> {
+        self.calibration_data.as_mut()
     }
 }
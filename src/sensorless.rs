@@ -0,0 +1,303 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use log::debug;
+use log::warn;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::calibration::MotionCalibration;
+use crate::config::SensorConfig;
+use crate::config::TableConfig;
+use crate::metrics::METRICS;
+use crate::motor::MoveDirection;
+use crate::primitives::Centimeter;
+use crate::sensor::DistanceSensor;
+use crate::sensor::SensorCalibrationData;
+use crate::storage::write_atomically;
+
+// The distance the table may travel without a sensor before its estimated
+// height is considered too inaccurate and it needs to be homed again
+const MAX_TRAVEL_SINCE_HOMING_CM: f32 = 300.0;
+
+/// A stand-in for a distance sensor for tables without one.
+///
+/// The height is estimated from the table's travel speed and the time the
+/// motor has been running, starting from the last known position which is
+/// persisted between runs.
+#[derive(Debug)]
+pub(crate) struct Sensorless {
+    calibration_file_path: PathBuf,
+    // Either loaded from the calibration file or derived from the configured
+    // travel times
+    calibration_data: Option<SensorCalibrationData>,
+    position_file_path: PathBuf,
+    position: TablePosition,
+    min_height: Centimeter,
+    max_height: Centimeter,
+    // The direction and start time of the current movement, if any
+    movement: Option<(MoveDirection, Instant)>,
+}
+
+/// The last known position of the table.
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
+struct TablePosition {
+    // The estimated height, unknown before homing for the first time
+    height_cm: Option<f32>,
+    // Whether the table was moving when the position was persisted, in which
+    // case the program has been interrupted and the height is unknown
+    moving: bool,
+    // The distance travelled since the table was last at a known height
+    travelled_since_homing_cm: f32,
+}
+
+impl Sensorless {
+    /// Creates a new [Sensorless] instance, restoring the last known position
+    /// from the position file.
+    ///
+    /// The table's speed is taken from the calibration file if available,
+    /// e.g. because it has been calibrated with a sensor once, and otherwise
+    /// from the configured travel times.
+    pub(crate) fn new(
+        config: SensorConfig,
+        table_config: TableConfig,
+    ) -> Self {
+        let calibration_file_path = config.calibration_file;
+        let calibration_data = SensorCalibrationData::load(&calibration_file_path)
+            .expect("calibration data must be readable")
+            .filter(|calibration_data| calibration_data.motion.is_some())
+            .or_else(|| {
+                let travel_cm = (table_config.max_table_height_cm
+                    - table_config.min_table_height_cm)
+                    .into_inner() as f32;
+                let up_travel_secs = config.up_travel_secs?;
+                let down_travel_secs = config.down_travel_secs?;
+                Some(SensorCalibrationData {
+                    min_height: table_config.min_table_height_cm,
+                    max_height: table_config.max_table_height_cm,
+                    motion: Some(MotionCalibration {
+                        up_travel_secs,
+                        down_travel_secs,
+                        up_speed_cm_per_sec: travel_cm / up_travel_secs,
+                        down_speed_cm_per_sec: travel_cm / down_travel_secs,
+                        start_latency_secs: 0.0,
+                        stop_latency_secs: 0.0,
+                    }),
+                    ..Default::default()
+                })
+            });
+        if calibration_data.is_none() {
+            warn!(
+                "No table speed known, configure `sensor.up_travel_secs` and \
+                 `sensor.down_travel_secs`"
+            );
+        }
+        let position_file_path = config.position_file;
+        let mut position = load_position(&position_file_path).expect("position must be readable");
+        if position.moving {
+            warn!("The last movement has been interrupted, the table position is unknown");
+            position.height_cm = None;
+            position.moving = false;
+        }
+        Self {
+            calibration_file_path,
+            calibration_data,
+            position_file_path,
+            position,
+            min_height: table_config.min_table_height_cm,
+            max_height: table_config.max_table_height_cm,
+            movement: None,
+        }
+    }
+
+    fn motion(&self) -> Result<&MotionCalibration> {
+        self.calibration_data
+            .as_ref()
+            .and_then(|calibration_data| calibration_data.motion.as_ref())
+            .ok_or_else(|| anyhow!("the table speed is unknown"))
+    }
+
+    /// The distance in centimeters the table has moved during the current
+    /// movement, including the distance it keeps moving after stopping the
+    /// motor if `stopping` is set.
+    fn distance_moved_cm(
+        &self,
+        stopping: bool,
+    ) -> Result<f32> {
+        let Some((direction, start)) = self.movement else {
+            return Ok(0.0);
+        };
+        let motion = self.motion()?;
+        let speed_cm_per_sec = match direction {
+            MoveDirection::Up => motion.up_speed_cm_per_sec,
+            MoveDirection::Down => motion.down_speed_cm_per_sec,
+        };
+        let mut moving_secs = start.elapsed().as_secs_f32() - motion.start_latency_secs;
+        if stopping && moving_secs > 0.0 {
+            moving_secs += motion.stop_latency_secs;
+        }
+        let distance_cm = speed_cm_per_sec * moving_secs.max(0.0);
+        Ok(match direction {
+            MoveDirection::Up => distance_cm,
+            MoveDirection::Down => -distance_cm,
+        })
+    }
+
+    fn persist_position(&self) {
+        let result = toml::to_string(&self.position)
+            .map_err(anyhow::Error::from)
+            .and_then(|raw_position| {
+                write_atomically(&self.position_file_path, raw_position.as_bytes())
+            });
+        if let Err(e) = result {
+            warn!(
+                "Could not persist the table position to {:?}: {e}",
+                self.position_file_path
+            );
+        }
+    }
+}
+
+impl DistanceSensor for Sensorless {
+    /// Estimates the current height from the last known position and the
+    /// current movement.
+    fn current_height(&mut self) -> Result<Centimeter> {
+        let height_cm = self
+            .position
+            .height_cm
+            .ok_or_else(|| anyhow!("the table position is unknown"))?;
+        let height_cm = (height_cm + self.distance_moved_cm(false)?).clamp(
+            self.min_height.into_inner() as f32,
+            self.max_height.into_inner() as f32,
+        );
+        let height = Centimeter(height_cm.round() as u8);
+        debug!("Estimated height is {height:?}");
+        METRICS.set_current_height(height);
+        Ok(height)
+    }
+
+    fn set_min_height(
+        &mut self,
+        _height: Centimeter,
+    ) -> Result<()> {
+        Err(anyhow!("cannot calibrate without a distance sensor"))
+    }
+
+    fn set_max_height(
+        &mut self,
+        _height: Centimeter,
+    ) -> Result<()> {
+        Err(anyhow!("cannot calibrate without a distance sensor"))
+    }
+
+    fn echo_duration(&mut self) -> Result<Duration> {
+        Err(anyhow!("there is no distance sensor"))
+    }
+
+    fn calibration_samples(&self) -> u8 {
+        0
+    }
+
+    fn restore_calibration_data(
+        &mut self,
+        calibration_data: Option<SensorCalibrationData>,
+    ) {
+        self.calibration_data = calibration_data;
+    }
+
+    fn calibration_file(&self) -> &Path {
+        &self.calibration_file_path
+    }
+
+    fn calibration_data(&self) -> Option<&SensorCalibrationData> {
+        self.calibration_data.as_ref()
+    }
+
+    fn calibration_data_mut(&mut self) -> Option<&mut SensorCalibrationData> {
+        self.calibration_data.as_mut()
+    }
+
+    fn measures_height(&self) -> bool {
+        false
+    }
+
+    fn needs_homing(&self) -> bool {
+        self.position.height_cm.is_none()
+            || self.position.travelled_since_homing_cm > MAX_TRAVEL_SINCE_HOMING_CM
+    }
+
+    fn movement_started(
+        &mut self,
+        direction: MoveDirection,
+    ) {
+        self.movement = Some((direction, Instant::now()));
+        // Persist that the table is moving so that an interrupted movement
+        // leaves the position unknown.
+        self.position.moving = true;
+        self.persist_position();
+    }
+
+    fn movement_stopped(&mut self) {
+        let distance_cm = match self.distance_moved_cm(true) {
+            Ok(distance_cm) => distance_cm,
+            Err(e) => {
+                warn!("Cannot estimate the distance moved: {e}");
+                self.movement = None;
+                self.position.height_cm = None;
+                self.persist_position();
+                return;
+            }
+        };
+        self.movement = None;
+        self.position.moving = false;
+        self.position.travelled_since_homing_cm += distance_cm.abs();
+        if let Some(height_cm) = self.position.height_cm {
+            let min_height_cm = self.min_height.into_inner() as f32;
+            let max_height_cm = self.max_height.into_inner() as f32;
+            let estimated_height_cm = height_cm + distance_cm;
+            // Overshooting a limit means the table was stopped by its end stop, so its
+            // position is known exactly again
+            if estimated_height_cm <= min_height_cm || estimated_height_cm >= max_height_cm {
+                self.position.travelled_since_homing_cm = 0.0;
+            }
+            self.position.height_cm = Some(estimated_height_cm.clamp(min_height_cm, max_height_cm));
+        }
+        debug!("Estimated position after moving: {:?}", self.position);
+        self.persist_position();
+    }
+
+    fn end_stop_reached(
+        &mut self,
+        height: Centimeter,
+    ) {
+        debug!("Table homed at {height:?}");
+        self.position = TablePosition {
+            height_cm: Some(height.into_inner() as f32),
+            moving: false,
+            travelled_since_homing_cm: 0.0,
+        };
+        self.persist_position();
+    }
+
+    fn end_stop_missed(&mut self) {
+        debug!("Table did not reach the end stop, its position is unknown");
+        self.position.height_cm = None;
+        self.persist_position();
+    }
+}
+
+/// Loads the last known table position, which is unknown if it has never
+/// been persisted.
+fn load_position(path: &Path) -> Result<TablePosition> {
+    match fs::read_to_string(path) {
+        Ok(raw_position) => Ok(toml::from_str(&raw_position)?),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(TablePosition::default()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,398 @@
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use log::debug;
+use log::info;
+use log::warn;
+
+use crate::config::Config;
+use crate::config::ControlConfig;
+use crate::config::WarningConfig;
+use crate::motor::MoveDirection;
+use crate::movement::Movement;
+use crate::primitives::Centimeter;
+use crate::shutdown::Shutdown;
+use crate::systemd;
+use crate::table::StandingDesk;
+use crate::telemetry::Trigger;
+use crate::warning;
+
+// How often to check for a shutdown and new connections while waiting for
+// commands
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// Clients must send a command within this time
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accepts commands on the control socket, one per line, while running as a
+/// long-lived service.
+///
+/// Each connection is read on its own thread, so that the desk can be stopped
+/// while it moves. Commands moving the desk are carried out one at a time.
+/// Each command is answered with a line starting with `ok` or `error`.
+#[derive(Debug)]
+pub(crate) struct Server {
+    listener: UnixListener,
+    // The socket file to remove on drop, unless the socket was passed by
+    // systemd
+    socket_path: Option<PathBuf>,
+    presets: Vec<(String, Centimeter)>,
+    // For cancelling and snoozing automated movements
+    warning: WarningConfig,
+}
+
+/// A command for the desk, sent from a connection to the thread controlling
+/// the desk.
+#[derive(Debug)]
+struct Request {
+    command: String,
+    // What caused the command, as set with the `trigger` command
+    trigger: Trigger,
+    response: Sender<Result<String>>,
+}
+
+/// A connection to a running service, for carrying out commands there instead
+/// of controlling the desk from another process.
+#[derive(Debug)]
+pub(crate) struct Client {
+    socket_path: PathBuf,
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Server {
+    /// Takes over the control socket passed by systemd or binds it at the
+    /// configured path, replacing a stale socket file.
+    pub(crate) fn bind(config: &Config) -> Result<Self> {
+        let (listener, socket_path) = match systemd::activated_socket()? {
+            Some(listener) => {
+                info!("Using the control socket passed by systemd");
+                (listener, None)
+            }
+            None => {
+                let path = &config.control.socket_path;
+                match fs::symlink_metadata(path) {
+                    Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+                    Ok(_) => return Err(anyhow!("{path:?} exists and is not a socket")),
+                    Err(e) if e.kind() == ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                info!("Listening on {path:?}");
+                (UnixListener::bind(path)?, Some(path.clone()))
+            }
+        };
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            socket_path,
+            presets: config.all_presets(),
+            warning: config.warning.clone(),
+        })
+    }
+
+    /// Serves commands until a shutdown is requested.
+    pub(crate) fn run(
+        &mut self,
+        table: &mut StandingDesk,
+        shutdown: &Shutdown,
+    ) {
+        systemd::notify_ready();
+        self.report_height(table);
+        let (requests, received_requests) = mpsc::channel();
+        while !shutdown.is_requested() {
+            systemd::ping_watchdog();
+            self.accept(&requests, shutdown);
+            match received_requests.recv_timeout(POLL_INTERVAL) {
+                Ok(Request {
+                    command,
+                    trigger,
+                    response,
+                }) => {
+                    // A stop only applies to the movement running at the time
+                    shutdown.resume();
+                    table.set_trigger(trigger);
+                    let _ = response.send(self.execute(&command, table));
+                    self.report_height(table);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => unreachable!("a sender is kept"),
+            }
+        }
+        systemd::notify_stopping();
+    }
+
+    /// Accepts the pending connections, reading each on its own thread.
+    fn accept(
+        &self,
+        requests: &Sender<Request>,
+        shutdown: &Shutdown,
+    ) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => self.spawn_connection(stream, requests, shutdown),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    warn!("Failed to accept control connection: {e}");
+                    return;
+                }
+            }
+        }
+    }
+
+    fn spawn_connection(
+        &self,
+        stream: UnixStream,
+        requests: &Sender<Request>,
+        shutdown: &Shutdown,
+    ) {
+        let requests = requests.clone();
+        let shutdown = shutdown.clone();
+        let warning = self.warning.clone();
+        let result = thread::Builder::new()
+            .name("control".to_string())
+            .spawn(move || {
+                if let Err(e) = handle_connection(stream, &requests, &shutdown, &warning) {
+                    warn!("Failed to handle control connection: {e:#}");
+                }
+            });
+        if let Err(e) = result {
+            warn!("Failed to start handling control connection: {e}");
+        }
+    }
+
+    fn execute(
+        &self,
+        command: &str,
+        table: &mut StandingDesk,
+    ) -> Result<String> {
+        let mut words = command.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let argument = words.next();
+        let centimeters = || -> Result<Centimeter> {
+            let argument = argument.ok_or_else(|| anyhow!("{name} needs a number"))?;
+            Ok(Centimeter(argument.parse()?))
+        };
+        match name {
+            "sit" => table.move_to_sitting()?,
+            "stand" => table.move_to_standing()?,
+            "move-to" => table.move_to_height(centimeters()?)?,
+            "up" => table.move_by(MoveDirection::Up, centimeters()?)?,
+            "down" => table.move_by(MoveDirection::Down, centimeters()?)?,
+            "preset" => {
+                let preset = argument.ok_or_else(|| anyhow!("preset needs a name"))?;
+                let (_, height) = self
+                    .presets
+                    .iter()
+                    .find(|(name, _)| name == preset)
+                    .ok_or_else(|| anyhow!("unknown preset {preset:?}"))?;
+                table.move_to_height(*height)?;
+            }
+            "height" => {}
+            "sample" => {
+                table.record_height_sample()?;
+            }
+            _ => {
+                return Err(anyhow!(
+                    "unknown command {name:?}, expected sit, stand, move-to, up, down, preset, \
+                     height, sample, trigger, stop, cancel or snooze"
+                ))
+            }
+        }
+        Ok(format!("{}", table.get_measurement()?.into_inner()))
+    }
+
+    fn report_height(
+        &self,
+        table: &mut StandingDesk,
+    ) {
+        match table.get_measurement() {
+            Ok(height) => systemd::notify_status(&format!("Height: {}cm", height.into_inner())),
+            Err(e) => systemd::notify_status(&format!("Height unknown: {e:#}")),
+        }
+    }
+}
+
+/// Reads the commands of a connection and answers them. Stopping the desk,
+/// cancelling and snoozing are handled right away, the other commands are
+/// passed on to the thread controlling the desk.
+fn handle_connection(
+    stream: UnixStream,
+    requests: &Sender<Request>,
+    shutdown: &Shutdown,
+    warning: &WarningConfig,
+) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut trigger = Trigger::Cli;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let command = line.trim();
+        if command.is_empty() {
+            continue;
+        }
+        debug!("Control command: {command}");
+        let response = match execute_immediately(command, &mut trigger, shutdown, warning) {
+            Some(result) => result,
+            None => {
+                let (response, received_response) = mpsc::channel();
+                requests.send(Request {
+                    command: command.to_string(),
+                    trigger,
+                    response,
+                })?;
+                // The desk thread only drops the request when shutting down
+                received_response
+                    .recv()
+                    .unwrap_or_else(|_| Err(anyhow!("the service is shutting down")))
+            }
+        };
+        let response = match response {
+            Ok(message) => format!("ok {message}"),
+            Err(e) => format!("error {e:#}"),
+        };
+        writeln!(writer, "{}", response.trim_end())?;
+    }
+    Ok(())
+}
+
+/// Carries out the commands which don't need the desk, returning `None` for
+/// the other commands.
+fn execute_immediately(
+    command: &str,
+    trigger: &mut Trigger,
+    shutdown: &Shutdown,
+    warning: &WarningConfig,
+) -> Option<Result<String>> {
+    let mut words = command.split_whitespace();
+    match words.next().unwrap_or_default() {
+        "trigger" => {
+            let name = words.next().unwrap_or_default();
+            Some(
+                match Trigger::ALL
+                    .into_iter()
+                    .find(|trigger| trigger.name() == name)
+                {
+                    Some(new_trigger) => {
+                        *trigger = new_trigger;
+                        Ok(String::new())
+                    }
+                    None => Err(anyhow!(
+                        "trigger needs one of cli, schedule, reminder or button"
+                    )),
+                },
+            )
+        }
+        "stop" => {
+            info!("Stopping the current movement");
+            shutdown.halt();
+            Some(Ok(String::new()))
+        }
+        "cancel" => Some(warning::snooze(warning, Duration::ZERO).map(|()| String::new())),
+        "snooze" => Some(words.next().map_or_else(
+            || Err(anyhow!("snooze needs a number of minutes")),
+            |mins| {
+                let mins: u64 = mins.parse()?;
+                warning::snooze(warning, Duration::from_secs(mins.saturating_mul(60)))?;
+                Ok(String::new())
+            },
+        )),
+        _ => None,
+    }
+}
+
+impl Client {
+    /// Connects to the control socket, if the service is running.
+    pub(crate) fn connect(config: &ControlConfig) -> Result<Option<Self>> {
+        let stream = match UnixStream::connect(&config.socket_path) {
+            Ok(stream) => stream,
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        // Waiting for an answer is interrupted regularly to check for a
+        // shutdown
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        Ok(Some(Self {
+            socket_path: config.socket_path.clone(),
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        }))
+    }
+
+    /// Sends a command and waits for the answer, returning the message of an
+    /// `ok` answer.
+    ///
+    /// The desk is stopped if a shutdown is requested while waiting, e.g.
+    /// with Ctrl-C, as it would be when controlled by this process.
+    ///
+    /// # Errors
+    /// Errors if the service answers with an error or the connection fails.
+    pub(crate) fn send(
+        &mut self,
+        command: &str,
+        shutdown: &Shutdown,
+    ) -> Result<String> {
+        writeln!(self.writer, "{command}")?;
+        let mut answer = vec![];
+        let mut stop_sent = false;
+        loop {
+            match self.reader.read_until(b'\n', &mut answer) {
+                Ok(0) => return Err(anyhow!("the service closed the connection")),
+                Ok(_) => break,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if shutdown.is_requested() && !stop_sent {
+                        info!("Stopping the movement of the service");
+                        self.send_stop()?;
+                        stop_sent = true;
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let answer = String::from_utf8(answer)?;
+        let answer = answer.trim_end();
+        if let Some(message) = answer.strip_prefix("ok") {
+            Ok(message.trim_start().to_string())
+        } else if let Some(message) = answer.strip_prefix("error") {
+            Err(anyhow!("{}", message.trim_start()))
+        } else {
+            Err(anyhow!("unexpected answer {answer:?}"))
+        }
+    }
+
+    /// Stops the movement of the service on a separate connection, as this
+    /// one is busy until the movement ends.
+    fn send_stop(&self) -> io::Result<()> {
+        let mut stream = UnixStream::connect(&self.socket_path)?;
+        writeln!(stream, "stop")?;
+        BufReader::new(stream).read_line(&mut String::new())?;
+        Ok(())
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Some(path) = &self.socket_path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
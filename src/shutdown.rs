@@ -0,0 +1,68 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use log::info;
+use log::warn;
+use signal_hook::consts::SIGUSR1;
+use simple_signal::Signal;
+
+/// A shutdown request shared between the signal handler and everything that
+/// runs for a while, e.g. the motor.
+///
+/// Unlike a message on a channel, a request is never consumed, so every part
+/// of a longer running command such as the calibration sees it.
+///
+/// The current movement can also be halted without shutting down, e.g. by
+/// `standup stop` while running as a service.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Shutdown {
+    requested: Arc<AtomicBool>,
+    // Set until the next command starts
+    halted: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    /// Creates a new [Shutdown] which is requested on SIGINT, SIGTERM and
+    /// SIGHUP, and which halts the current movement on SIGUSR1.
+    pub(crate) fn on_signals() -> Self {
+        let shutdown = Self::default();
+        let handler_shutdown = shutdown.clone();
+        simple_signal::set_handler(&[Signal::Int, Signal::Term, Signal::Hup], move |signals| {
+            info!("Received {signals:?}, shutting down");
+            handler_shutdown.request();
+        });
+        if let Err(e) = signal_hook::flag::register(SIGUSR1, Arc::clone(&shutdown.halted)) {
+            warn!("Cannot halt movements on SIGUSR1: {e}");
+        }
+        shutdown
+    }
+
+    /// Requests a shutdown.
+    pub(crate) fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether a shutdown has been requested.
+    pub(crate) fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Halts the current movement, including a calibration, without shutting
+    /// down.
+    pub(crate) fn halt(&self) {
+        self.halted.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the current movement must stop, because it has been halted or a
+    /// shutdown has been requested.
+    pub(crate) fn is_halted(&self) -> bool {
+        self.halted.load(Ordering::SeqCst) || self.is_requested()
+    }
+
+    /// Lets movements run again after a halt, called before starting the next
+    /// command.
+    pub(crate) fn resume(&self) {
+        self.halted.store(false, Ordering::SeqCst);
+    }
+}
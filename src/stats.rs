@@ -0,0 +1,172 @@
+use std::time::Duration;
+use std::time::SystemTime;
+
+use chrono::Local;
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use crate::config::GoalConfig;
+use crate::telemetry::local_date;
+use crate::telemetry::PostureInterval;
+
+/// Statistics about the time spent sitting and standing in some period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct Stats {
+    #[serde(with = "crate::storage::rfc3339")]
+    pub from: SystemTime,
+    #[serde(with = "crate::storage::rfc3339")]
+    pub to: SystemTime,
+    pub sitting_secs: u64,
+    pub standing_secs: u64,
+    // The longest uninterrupted time spent sitting
+    pub longest_sitting_streak_secs: u64,
+    // The progress towards today's standing goal, if one is configured
+    pub goal: Option<GoalProgress>,
+}
+
+/// The progress towards the daily standing goal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct GoalProgress {
+    pub goal_secs: u64,
+    pub standing_today_secs: u64,
+    // The standing time needed by now to reach the goal at the end of the
+    // workday when standing evenly throughout it
+    pub expected_by_now_secs: u64,
+    pub behind_schedule: bool,
+}
+
+/// Computes the statistics for the period between `from` and `now`.
+pub(crate) fn compute_stats(
+    intervals: &[PostureInterval],
+    from: SystemTime,
+    now: SystemTime,
+    goal: Option<&GoalConfig>,
+) -> Stats {
+    let mut sitting = Duration::ZERO;
+    let mut standing = Duration::ZERO;
+    let mut longest_sitting_streak = Duration::ZERO;
+    let mut sitting_streak = Duration::ZERO;
+    let mut previous_end = None;
+    for interval in intervals {
+        let duration = clipped_duration(interval, from, now);
+        if interval.standing {
+            standing += duration;
+            sitting_streak = Duration::ZERO;
+        } else {
+            sitting += duration;
+            // Sitting only counts as uninterrupted if there's no gap in the records
+            if previous_end != Some(interval.start) {
+                sitting_streak = Duration::ZERO;
+            }
+            sitting_streak += duration;
+            longest_sitting_streak = longest_sitting_streak.max(sitting_streak);
+        }
+        previous_end = Some(interval.end);
+    }
+    Stats {
+        from,
+        to: now,
+        sitting_secs: sitting.as_secs(),
+        standing_secs: standing.as_secs(),
+        longest_sitting_streak_secs: longest_sitting_streak.as_secs(),
+        goal: goal.map(|goal| goal_progress(intervals, goal, now)),
+    }
+}
+
+/// Computes the progress towards today's standing goal.
+pub(crate) fn goal_progress(
+    intervals: &[PostureInterval],
+    goal: &GoalConfig,
+    now: SystemTime,
+) -> GoalProgress {
+    let today = local_date(now);
+    let start_of_day = local_time(today, 0).unwrap_or(now);
+    let standing_today = intervals
+        .iter()
+        .filter(|interval| interval.standing)
+        .map(|interval| clipped_duration(interval, start_of_day, now))
+        .sum::<Duration>();
+
+    let goal_duration = Duration::from_secs(goal.daily_standing_mins.saturating_mul(60));
+    let (expected_by_now, during_workday) = match (
+        local_time(today, goal.workday_start_hour),
+        local_time(today, goal.workday_end_hour),
+    ) {
+        (Some(workday_start), Some(workday_end)) if workday_start < workday_end => {
+            let workday = workday_end
+                .duration_since(workday_start)
+                .unwrap_or_default();
+            let elapsed = now
+                .duration_since(workday_start)
+                .unwrap_or_default()
+                .min(workday);
+            (
+                goal_duration.mul_f64(elapsed.as_secs_f64() / workday.as_secs_f64()),
+                workday_start <= now && now < workday_end,
+            )
+        }
+        // Without a proper workday the goal is simply due at the end of the day
+        _ => (Duration::ZERO, false),
+    };
+    GoalProgress {
+        goal_secs: goal_duration.as_secs(),
+        standing_today_secs: standing_today.as_secs(),
+        expected_by_now_secs: expected_by_now.as_secs(),
+        // Falling behind only matters while there is still time to stand
+        behind_schedule: during_workday && standing_today < expected_by_now,
+    }
+}
+
+/// The part of the interval's duration that lies between `from` and `to`.
+fn clipped_duration(
+    interval: &PostureInterval,
+    from: SystemTime,
+    to: SystemTime,
+) -> Duration {
+    let start = interval.start.max(from);
+    let end = interval.end.min(to);
+    end.duration_since(start).unwrap_or_default()
+}
+
+/// The time at the full hour on the given local date.
+pub(crate) fn local_time(
+    date: NaiveDate,
+    hour: u32,
+) -> Option<SystemTime> {
+    date.and_hms_opt(hour, 0, 0)?
+        .and_local_timezone(Local)
+        .earliest()
+        .map(SystemTime::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOAL: GoalConfig = GoalConfig {
+        daily_standing_mins: 120,
+        workday_start_hour: 9,
+        workday_end_hour: 17,
+    };
+
+    fn today_at(hour: u32) -> SystemTime {
+        local_time(local_date(SystemTime::now()), hour).unwrap()
+    }
+
+    #[test]
+    fn is_behind_schedule_during_the_workday() {
+        let progress = goal_progress(&[], &GOAL, today_at(13));
+
+        assert_eq!(progress.expected_by_now_secs, 60 * 60);
+        assert!(progress.behind_schedule);
+    }
+
+    #[test]
+    fn is_not_behind_schedule_after_the_workday() {
+        let progress = goal_progress(&[], &GOAL, today_at(20));
+
+        assert_eq!(progress.expected_by_now_secs, 120 * 60);
+        assert_eq!(progress.standing_today_secs, 0);
+        assert!(!progress.behind_schedule);
+    }
+}
@@ -0,0 +1,78 @@
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Result;
+
+/// Writes `contents` to `path` atomically.
+///
+/// The data is first written to a temporary file next to `path` which is then
+/// renamed, so that a crash mid-write never leaves a truncated file behind.
+/// Missing parent directories are created.
+pub(crate) fn write_atomically<P: AsRef<Path>>(
+    path: P,
+    contents: &[u8],
+) -> Result<()> {
+    let path = path.as_ref();
+    let parent = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    if let Some(dir) = parent {
+        fs::create_dir_all(dir)?;
+    }
+    let mut tmp_file_name = path.file_name().map(OsString::from).unwrap_or_default();
+    tmp_file_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_file_name);
+
+    let mut tmp_file = File::create(&tmp_path)?;
+    tmp_file.write_all(contents)?;
+    tmp_file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    // Make sure the rename itself is persisted.
+    if let Some(dir) = parent {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// (De-)serializes timestamps as RFC 3339 strings.
+pub(crate) mod rfc3339 {
+    use std::time::SystemTime;
+
+    use serde::de::Error;
+    use serde::Deserialize;
+    use serde::Deserializer;
+    use serde::Serializer;
+
+    pub(crate) fn serialize<S: Serializer>(
+        time: &SystemTime,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&humantime::format_rfc3339_seconds(*time))
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D
+    ) -> Result<SystemTime, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        humantime::parse_rfc3339_weak(&raw).map_err(D::Error::custom)
+    }
+}
+
+/// (De-)serializes timestamps as RFC 3339 strings with nanoseconds, for
+/// timestamps which are compared with each other.
+pub(crate) mod rfc3339_nanos {
+    use std::time::SystemTime;
+
+    use serde::Serializer;
+
+    pub(crate) use super::rfc3339::deserialize;
+
+    pub(crate) fn serialize<S: Serializer>(
+        time: &SystemTime,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&humantime::format_rfc3339_nanos(*time))
+    }
+}
@@ -0,0 +1,171 @@
+use std::env;
+use std::fs;
+use std::os::fd::FromRawFd;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use log::debug;
+use log::Level;
+use once_cell::sync::Lazy;
+use sd_notify::NotifyState;
+
+use crate::config::ControlConfig;
+
+/// The systemd watchdog of this process, if the service manager expects
+/// watchdog pings.
+static WATCHDOG: Lazy<Option<Watchdog>> = Lazy::new(Watchdog::from_env);
+
+#[derive(Debug)]
+struct Watchdog {
+    // Pings are sent at least this often, half the watchdog timeout
+    interval: Duration,
+    last_ping: Mutex<Option<Instant>>,
+}
+
+impl Watchdog {
+    fn from_env() -> Option<Self> {
+        let mut timeout_usec = 0;
+        if !sd_notify::watchdog_enabled(false, &mut timeout_usec) {
+            return None;
+        }
+        let interval = Duration::from_micros(timeout_usec) / 2;
+        debug!("Pinging the systemd watchdog every {interval:?}");
+        Some(Self {
+            interval,
+            last_ping: Mutex::new(None),
+        })
+    }
+}
+
+/// Tells systemd that the service is ready.
+pub(crate) fn notify_ready() {
+    notify(&[NotifyState::Ready]);
+}
+
+/// Tells systemd that the service is shutting down.
+pub(crate) fn notify_stopping() {
+    notify(&[NotifyState::Stopping]);
+}
+
+/// Shows a status line in `systemctl status`.
+pub(crate) fn notify_status(status: &str) {
+    notify(&[NotifyState::Status(status)]);
+}
+
+/// Pings the systemd watchdog, if enabled. Called from the loops which must
+/// keep making progress, e.g. the motor control loop, so that a hanging
+/// process is restarted. Pings more frequent than half the watchdog timeout
+/// are skipped.
+pub(crate) fn ping_watchdog() {
+    let Some(watchdog) = &*WATCHDOG else {
+        return;
+    };
+    let mut last_ping = watchdog.last_ping.lock().unwrap_or_else(|e| e.into_inner());
+    if last_ping.is_some_and(|last_ping| last_ping.elapsed() < watchdog.interval) {
+        return;
+    }
+    notify(&[NotifyState::Watchdog]);
+    *last_ping = Some(Instant::now());
+}
+
+fn notify(state: &[NotifyState]) {
+    // Outside of systemd there is nobody to notify, which is not an error
+    if let Err(e) = sd_notify::notify(false, state) {
+        debug!("Could not notify systemd: {e}");
+    }
+}
+
+/// The control socket passed by systemd with socket activation, if any.
+pub(crate) fn activated_socket() -> Result<Option<UnixListener>> {
+    let Some(fd) = sd_notify::listen_fds()?.next() else {
+        return Ok(None);
+    };
+    // SAFETY: systemd passes the listening socket as an open file descriptor
+    // owned by this process from here on.
+    Ok(Some(unsafe { UnixListener::from_raw_fd(fd) }))
+}
+
+/// Whether the logs are written to the systemd journal.
+pub(crate) fn logs_to_journal() -> bool {
+    env::var_os("JOURNAL_STREAM").is_some()
+}
+
+/// The syslog priority of a log level, prefixed to log lines so that journald
+/// records the level.
+pub(crate) fn journal_priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Where the unit files are installed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ServiceScope {
+    /// A service of the current user.
+    User,
+    /// A system-wide service.
+    System,
+}
+
+/// The unit files running `standup serve` with the given configuration as a
+/// socket-activated service, by file name.
+pub(crate) fn unit_files(
+    config_file: &Path,
+    control: &ControlConfig,
+    scope: ServiceScope,
+) -> Result<Vec<(&'static str, String)>> {
+    let executable = env::current_exe()?;
+    let config_file = fs::canonicalize(config_file)?;
+    let wanted_by = match scope {
+        ServiceScope::User => "default.target",
+        ServiceScope::System => "multi-user.target",
+    };
+    let socket = format!(
+        "[Unit]\nDescription=Standing desk control socket\n\n[Socket]\nListenStream={}\nSocketMode=0660\n\n[Install]\nWantedBy=sockets.target\n",
+        control.socket_path.display()
+    );
+    let service = format!(
+        "[Unit]\nDescription=Standing desk control\nRequires=standup.socket\nAfter=standup.socket\n\n[Service]\nType=notify\nExecStart={} -c {} -dd serve\nWatchdogSec=30\nRestart=on-failure\n\n[Install]\nWantedBy={wanted_by}\n",
+        quote_exec_arg(&executable),
+        quote_exec_arg(&config_file)
+    );
+    Ok(vec![
+        ("standup.socket", socket),
+        ("standup.service", service),
+    ])
+}
+
+/// Quotes a path for the command line of a unit file, escaping the characters
+/// systemd would interpret otherwise, e.g. in paths containing spaces.
+fn quote_exec_arg(path: &Path) -> String {
+    let escaped = path
+        .display()
+        .to_string()
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{escaped}\"")
+}
+
+/// The directory unit files of the scope are installed to.
+pub(crate) fn unit_dir(scope: ServiceScope) -> Result<PathBuf> {
+    match scope {
+        ServiceScope::System => Ok(PathBuf::from("/etc/systemd/system")),
+        ServiceScope::User => env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("systemd/user"))
+            .ok_or_else(|| anyhow!("cannot find the user's config directory")),
+    }
+}
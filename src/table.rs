@@ -1,84 +1,420 @@
-use std::fs;
-use std::sync::mpsc::Receiver;
+use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
 
 use anyhow::anyhow;
+use anyhow::Context;
 use anyhow::Result;
 use log::debug;
 use log::info;
+use log::warn;
 
+use crate::calibration;
+use crate::calibration::CalibrationMetadata;
+use crate::calibration::CalibrationStore;
+use crate::calibration::MotionCalibration;
+use crate::calibration::PlateauDetector;
 use crate::config::Config;
+use crate::config::SensorKind;
 use crate::config::TableConfig;
+use crate::feedback::Beep;
+use crate::feedback::Feedback;
+use crate::feedback::Status;
+use crate::metrics::METRICS;
 use crate::motor::DeskMotorDriver;
 use crate::motor::MotorDriver;
+use crate::motor::MoveDirection;
 use crate::movement::Movement;
+use crate::presence::Presence;
 use crate::primitives::Centimeter;
 use crate::sensor::DistanceSensor;
 use crate::sensor::HCSR04;
+use crate::sensorless::Sensorless;
+use crate::shutdown::Shutdown;
+use crate::telemetry::HeightSample;
+use crate::telemetry::MovementOutcome;
+use crate::telemetry::MovementRecord;
+use crate::telemetry::Telemetry;
+use crate::telemetry::TelemetryEvent;
+use crate::telemetry::Trigger;
+use crate::warning::MoveWarning;
+use crate::webhooks::CalibrationFinished;
+use crate::webhooks::MovementStarted;
+use crate::webhooks::WebhookEvent;
+use crate::webhooks::Webhooks;
+
+// How long to move the table up when measuring the stop latency
+const STOP_LATENCY_PROBE_DURATION: Duration = Duration::from_secs(2);
+// The longest time to wait for the table to come to a halt after stopping the
+// motor
+const MAX_STOP_LATENCY: Duration = Duration::from_secs(3);
+// Moving to an end stop without a sensor takes a bit longer than the calibrated
+// travel time to make sure the end stop is reached
+const END_STOP_TIME_MARGIN: f32 = 1.2;
+// Movements may take a bit longer than estimated from the calibrated speed,
+// which varies with the load on the table
+const RUN_TIME_MARGIN: f32 = 1.2;
+// How far the table may be off the target height, as moving the table and the
+// height measurement are not so precise
+const HEIGHT_TOLERANCE: Centimeter = Centimeter(1);
 
 /// The standing desk implementation.
 #[derive(Debug)]
-pub(crate) struct StandingDesk<S: DistanceSensor = HCSR04, M: MotorDriver = DeskMotorDriver> {
+pub(crate) struct StandingDesk<
+    S: DistanceSensor = Box<dyn DistanceSensor>,
+    M: MotorDriver = DeskMotorDriver,
+> {
     config: TableConfig,
     sensor: S,
     motor_driver: M,
+    telemetry: Telemetry,
+    // What causes the movements, for telemetry
+    trigger: Trigger,
+    // Consulted before automated movements
+    presence: Presence,
+    // Given before automated movements
+    warning: MoveWarning,
+    // The buzzer and status LED
+    feedback: Feedback,
+    webhooks: Webhooks,
+    // Halted once a movement is aborted, so that the rest of it is not
+    // carried out either
+    shutdown: Shutdown,
+    // Aborts movements while the motor runs once it returns true
+    abort: Option<fn() -> bool>,
 }
 
 impl StandingDesk {
-    /// Creates a new instance of a `StandingDesk`.
+    /// Creates a new instance of a `StandingDesk` with the configured kind of
+    /// sensor.
     pub fn new(
         config: Config,
-        shutdown_rx: Receiver<()>,
+        shutdown: Shutdown,
     ) -> Self {
-        let sensor = HCSR04::new(config.sensor);
-        let motor_driver = DeskMotorDriver::new(config.motor, shutdown_rx);
+        let sensor: Box<dyn DistanceSensor> = match config.sensor.kind {
+            SensorKind::Hcsr04 => Box::new(HCSR04::new(config.sensor)),
+            SensorKind::Sensorless => Box::new(Sensorless::new(config.sensor, config.table)),
+        };
+        let warning = MoveWarning::new(&config.warning, shutdown.clone());
+        let motor_driver = DeskMotorDriver::new(&config.motor, shutdown.clone());
         Self {
             config: config.table,
             sensor,
             motor_driver,
+            telemetry: Telemetry::new(&config.telemetry),
+            trigger: Trigger::Cli,
+            presence: Presence::new(&config.presence),
+            warning,
+            feedback: Feedback::new(&config.feedback),
+            webhooks: Webhooks::new(&config.webhooks),
+            shutdown,
+            abort: None,
         }
     }
 
     pub fn get_measurement(&mut self) -> Result<Centimeter> {
         self.sensor.current_height()
     }
-}
 
-impl<S: DistanceSensor, M: MotorDriver> Movement for StandingDesk<S, M> {
-    fn move_to_standing(&mut self) -> Result<()> {
-        info!("Moving to standing position ...");
-        self.move_to_height(self.config.standing_height_cm)
+    /// Measures the current height and records it in the telemetry log.
+    pub fn record_height_sample(&mut self) -> Result<Centimeter> {
+        let height = self.sensor.current_height()?;
+        self.telemetry
+            .record(&TelemetryEvent::HeightSample(HeightSample {
+                measured_at: SystemTime::now(),
+                height,
+            }));
+        Ok(height)
     }
 
-    fn move_to_sitting(&mut self) -> Result<()> {
-        info!("Moving to standing position ...");
-        self.move_to_height(self.config.sitting_height_cm)
+    /// Sets what causes the following movements, for telemetry.
+    pub fn set_trigger(
+        &mut self,
+        trigger: Trigger,
+    ) {
+        self.trigger = trigger;
     }
 
-    fn calibrate(&mut self) -> Result<()> {
-        // Move the table until a timeout is reached.
-        // The assumption is that the timeout is long enough so that the table
-        // physically moves to its heighest and lowest positions in that timeframe.
-        // The highest and lowest positions are defined in the configuration data, and
-        // are calibrated for accordingly.
+    /// Whether calibration data for the sensor is available.
+    pub fn is_calibrated(&self) -> bool {
+        self.sensor.calibration_data().is_some()
+    }
 
-        info!("Calibrating");
-        self.motor_driver.up_until_false_or_timeout(&mut || true);
+    /// The path of the sensor's calibration file.
+    pub fn calibration_file(&self) -> &Path {
+        self.sensor.calibration_file()
+    }
+}
+
+impl<S: DistanceSensor, M: MotorDriver> StandingDesk<S, M> {
+    /// Moves the table to its highest and lowest positions, measures the
+    /// sensor's reference values and the travel times there and checks them
+    /// for plausibility.
+    fn measure_calibration(
+        &mut self,
+        temperature_celsius: Option<f32>,
+    ) -> Result<()> {
+        // Start from the lowest position so that the full travel time up can be
+        // measured.
+        self.move_to_end_stop(MoveDirection::Down)?;
+
+        let up_timings = self.move_to_end_stop(MoveDirection::Up)?;
+        debug!("Timings up: {up_timings:?}");
         self.sensor
             .set_max_height(self.config.max_table_height_cm)?;
 
-        self.motor_driver.down_until_false_or_timeout(&mut || true);
+        let down_timings = self.move_to_end_stop(MoveDirection::Down)?;
+        debug!("Timings down: {down_timings:?}");
         self.sensor
             .set_min_height(self.config.min_table_height_cm)?;
 
-        let calibration_file = self.sensor.calibration_file();
-        let raw_calibration_data = toml::to_string(&self.sensor.calibration_data())?;
-        fs::write(calibration_file, raw_calibration_data)?;
-        debug!("Calibration data written to {calibration_file:?}");
+        let stop_latency = self.measure_stop_latency()?;
+        debug!("Stop latency: {stop_latency:?}");
 
-        self.move_to_sitting()
+        let (Some(up_start_latency), Some(down_start_latency)) =
+            (up_timings.start_latency, down_timings.start_latency)
+        else {
+            return Err(anyhow!(
+                "the table did not move between its lowest and highest positions"
+            ));
+        };
+        let travel_cm =
+            (self.config.max_table_height_cm - self.config.min_table_height_cm).into_inner() as f32;
+        let motion = MotionCalibration {
+            up_travel_secs: up_timings.travel_time.as_secs_f32(),
+            down_travel_secs: down_timings.travel_time.as_secs_f32(),
+            up_speed_cm_per_sec: travel_cm
+                / up_timings
+                    .travel_time
+                    .saturating_sub(up_start_latency)
+                    .as_secs_f32(),
+            down_speed_cm_per_sec: travel_cm
+                / down_timings
+                    .travel_time
+                    .saturating_sub(down_start_latency)
+                    .as_secs_f32(),
+            start_latency_secs: (up_start_latency + down_start_latency).as_secs_f32() / 2.0,
+            stop_latency_secs: stop_latency.as_secs_f32(),
+        };
+
+        let sample_count = self.sensor.calibration_samples();
+        let calibration_data = self
+            .sensor
+            .calibration_data_mut()
+            .ok_or_else(|| anyhow!("calibration data missing after calibration"))?;
+        calibration_data.metadata = Some(CalibrationMetadata {
+            calibrated_at: SystemTime::now(),
+            temperature_celsius,
+            min_table_height_cm: self.config.min_table_height_cm,
+            max_table_height_cm: self.config.max_table_height_cm,
+            sample_count,
+        });
+        calibration_data.motion = Some(motion);
+        calibration::validate(calibration_data)
     }
 
-    fn move_to_height(
+    /// Moves the table in the given direction until it stops moving because it
+    /// reached its end stop.
+    ///
+    /// # Errors
+    /// Errors if the table did not stop moving before the motor timed out.
+    fn move_to_end_stop(
+        &mut self,
+        direction: MoveDirection,
+    ) -> Result<EndStopTimings> {
+        let mut plateau_detector = PlateauDetector::default();
+        let start = Instant::now();
+        // The travel time is yet to be measured
+        self.drive(direction, None, |sensor| match sensor.echo_duration() {
+            Ok(echo_duration) => !plateau_detector.push(Instant::now(), echo_duration),
+            Err(e) => {
+                // Keep moving on a failed measurement, the motor timeout still applies
+                debug!("Failed measurement while moving to end stop: {e}");
+                true
+            }
+        })?;
+        let plateau_start = plateau_detector.plateau_start().ok_or_else(|| {
+            anyhow!("the table did not stop moving {direction:?} before the motor timed out")
+        })?;
+        Ok(EndStopTimings {
+            travel_time: plateau_start.duration_since(start),
+            start_latency: plateau_detector
+                .movement_start()
+                .map(|movement_start| movement_start.duration_since(start)),
+        })
+    }
+
+    /// Measures how long the table keeps moving after the motor is stopped by
+    /// briefly moving it up from its lowest position.
+    fn measure_stop_latency(&mut self) -> Result<Duration> {
+        let start = Instant::now();
+        self.drive(MoveDirection::Up, Some(STOP_LATENCY_PROBE_DURATION), |_| {
+            start.elapsed() < STOP_LATENCY_PROBE_DURATION
+        })?;
+        let stop = Instant::now();
+        let mut plateau_detector = PlateauDetector::default();
+        while stop.elapsed() < MAX_STOP_LATENCY {
+            match self.sensor.echo_duration() {
+                Ok(echo_duration) => {
+                    if plateau_detector.push(Instant::now(), echo_duration) {
+                        break;
+                    }
+                }
+                // A single failed measurement does not tell whether the table
+                // is still moving, skip it
+                Err(e) => debug!("Failed measurement while measuring the stop latency: {e}"),
+            }
+        }
+        let plateau_start = plateau_detector
+            .plateau_start()
+            .ok_or_else(|| anyhow!("the table did not come to a halt after stopping the motor"))?;
+        Ok(plateau_start.saturating_duration_since(stop))
+    }
+
+    /// Moves the table in the given direction until the condition is false or
+    /// the motor times out, keeping the sensor informed about the movement.
+    ///
+    /// The condition is passed the sensor for taking measurements.
+    /// `expected_run_time` is the longest time the movement is expected to
+    /// take, if known, so that the motor can refuse it up front.
+    fn drive<F>(
+        &mut self,
+        direction: MoveDirection,
+        expected_run_time: Option<Duration>,
+        mut condition: F,
+    ) -> Result<()>
+    where
+        F: FnMut(&mut S) -> bool,
+    {
+        self.sensor.movement_started(direction);
+        let sensor = &mut self.sensor;
+        let shutdown = &self.shutdown;
+        let abort = self.abort;
+        let mut condition = || {
+            if abort.is_some_and(|abort| abort()) {
+                debug!("Movement aborted");
+                shutdown.halt();
+                return false;
+            }
+            condition(sensor)
+        };
+        let result = match direction {
+            MoveDirection::Up => self
+                .motor_driver
+                .up_until_false_or_timeout(expected_run_time, &mut condition),
+            MoveDirection::Down => self
+                .motor_driver
+                .down_until_false_or_timeout(expected_run_time, &mut condition),
+        };
+        self.sensor.movement_stopped();
+        result
+    }
+
+    /// Moves the table in the given direction for a fixed time. Returns
+    /// whether the table moved for the whole time, as opposed to being
+    /// stopped early, e.g. because the movement was halted or the motor
+    /// reached its duty cycle limit.
+    fn move_for(
+        &mut self,
+        direction: MoveDirection,
+        duration: Duration,
+    ) -> Result<bool> {
+        let start = Instant::now();
+        let mut completed = false;
+        self.drive(direction, Some(duration), |_| {
+            completed = start.elapsed() >= duration;
+            !completed
+        })?;
+        Ok(completed)
+    }
+
+    /// Moves the table to an end stop without the sensor by moving for a bit
+    /// longer than its calibrated travel time.
+    fn move_to_end_stop_blindly(
+        &mut self,
+        direction: MoveDirection,
+        motion: &MotionCalibration,
+    ) -> Result<()> {
+        debug!("Moving {direction:?} to the end stop");
+        let end_stop_time = motion.travel_time(direction).mul_f32(END_STOP_TIME_MARGIN);
+        if !self.move_for(direction, end_stop_time)? {
+            self.sensor.end_stop_missed();
+            return Err(anyhow!(
+                "stopped before reaching the end stop, the table height is unknown"
+            ));
+        }
+        let end_stop_height = match direction {
+            MoveDirection::Up => self.config.max_table_height_cm,
+            MoveDirection::Down => self.config.min_table_height_cm,
+        };
+        self.sensor.end_stop_reached(end_stop_height);
+        Ok(())
+    }
+
+    /// The table's speed learned during calibration.
+    fn motion_calibration(&self) -> Result<MotionCalibration> {
+        self.sensor
+            .calibration_data()
+            .and_then(|calibration_data| calibration_data.motion.clone())
+            .ok_or_else(|| anyhow!("no table speed has been calibrated yet"))
+    }
+
+    /// The longest time the motor is expected to run for moving the table by
+    /// `distance` in the given direction, if its speed has been calibrated.
+    fn expected_run_time(
+        &self,
+        direction: MoveDirection,
+        distance: Centimeter,
+    ) -> Option<Duration> {
+        self.motion_calibration().ok().map(|motion| {
+            motion
+                .run_time(direction, distance)
+                .mul_f32(RUN_TIME_MARGIN)
+        })
+    }
+
+    /// Moves the table to its lowest position so that its height is known
+    /// again.
+    fn home(&mut self) -> Result<()> {
+        info!("Table height is uncertain, moving to the lowest position first");
+        let motion = self.motion_calibration()?;
+        self.move_to_end_stop_blindly(MoveDirection::Down, &motion)
+    }
+
+    /// Moves to the given height without the sensor, using the table's speed
+    /// learned during calibration.
+    ///
+    /// The table is first moved to the end stop closer to the desired height
+    /// to start from a known position.
+    fn move_to_height_by_dead_reckoning(
+        &mut self,
+        height_cm: Centimeter,
+    ) -> Result<()> {
+        let motion = self.motion_calibration()?;
+        let min_height = self.config.min_table_height_cm;
+        let max_height = self.config.max_table_height_cm;
+        let (end_stop_direction, distance) = if height_cm - min_height <= max_height - height_cm {
+            (MoveDirection::Down, height_cm - min_height)
+        } else {
+            (MoveDirection::Up, max_height - height_cm)
+        };
+        self.move_to_end_stop_blindly(end_stop_direction, &motion)?;
+
+        let direction = match end_stop_direction {
+            MoveDirection::Up => MoveDirection::Down,
+            MoveDirection::Down => MoveDirection::Up,
+        };
+        if distance > Centimeter(0) {
+            let run_time = motion.run_time(direction, distance);
+            debug!("Moving {direction:?} by {distance:?} for {run_time:?}");
+            self.move_for(direction, run_time)?;
+        }
+        Ok(())
+    }
+
+    /// Moves to a specific height without recording telemetry.
+    fn move_to_height_untracked(
         &mut self,
         height_cm: Centimeter,
     ) -> Result<()> {
@@ -96,31 +432,523 @@ impl<S: DistanceSensor, M: MotorDriver> Movement for StandingDesk<S, M> {
             ));
         }
         info!("Moving to height {height_cm:?}");
-        let current_height = self.sensor.current_height()?;
-        // Allow for some tolerance as moving the table and the height measurement are
-        // not so precise
-        if height_cm - Centimeter(1) <= current_height
-            && current_height <= height_cm + Centimeter(1)
+        if self.sensor.needs_homing() {
+            self.home()?;
+        }
+        let current_height = match self.sensor.current_height() {
+            Ok(current_height) => current_height,
+            Err(e) => {
+                warn!("Cannot measure the height ({e}), moving by the calibrated table speed");
+                return self.move_to_height_by_dead_reckoning(height_cm);
+            }
+        };
+        if height_cm - HEIGHT_TOLERANCE <= current_height
+            && current_height <= height_cm + HEIGHT_TOLERANCE
         {
             debug!("Table already at desired height");
             return Ok(());
         }
+        let mut measurement_failed = false;
         if current_height < height_cm {
-            self.motor_driver.up_until_false_or_timeout(&mut || {
-                match self.sensor.current_height() {
-                    Err(_) => false, // Stop if there is an error in the measurement
+            let expected_run_time =
+                self.expected_run_time(MoveDirection::Up, height_cm - current_height);
+            self.drive(MoveDirection::Up, expected_run_time, |sensor| {
+                match sensor.current_height() {
+                    Err(_) => {
+                        // Stop if there is an error in the measurement
+                        measurement_failed = true;
+                        false
+                    }
                     Ok(current_height) => current_height < height_cm,
                 }
-            });
+            })?;
         }
         if current_height > height_cm {
-            self.motor_driver.down_until_false_or_timeout(&mut || {
-                match self.sensor.current_height() {
-                    Err(_) => false, // Stop if there is an error in the measurement
+            let expected_run_time =
+                self.expected_run_time(MoveDirection::Down, current_height - height_cm);
+            self.drive(MoveDirection::Down, expected_run_time, |sensor| {
+                match sensor.current_height() {
+                    Err(_) => {
+                        // Stop if there is an error in the measurement
+                        measurement_failed = true;
+                        false
+                    }
                     Ok(current_height) => current_height > height_cm,
                 }
-            })
+            })?;
+        }
+        if measurement_failed {
+            warn!("Height measurement failed while moving, moving by the calibrated table speed");
+            return self.move_to_height_by_dead_reckoning(height_cm);
         }
         Ok(())
     }
+
+    /// Records an automated movement to `height_cm` which did not happen.
+    fn record_skipped(
+        &mut self,
+        height_cm: Centimeter,
+    ) {
+        let now = SystemTime::now();
+        let height = self.sensor.current_height().ok();
+        METRICS.count_movement(MovementOutcome::Skipped);
+        let record = MovementRecord {
+            started_at: now,
+            ended_at: now,
+            from_height: height,
+            target_height: height_cm,
+            to_height: height,
+            outcome: MovementOutcome::Skipped,
+            trigger: self.trigger,
+            error: None,
+        };
+        self.telemetry
+            .record(&TelemetryEvent::Movement(record.clone()));
+        self.webhooks.send(&WebhookEvent::MovementSkipped(record));
+    }
+
+    /// Moves the table in the given direction while the condition, passed the
+    /// current height, is true, stopping at the table's height limits.
+    fn jog_while<F>(
+        &mut self,
+        direction: MoveDirection,
+        mut condition: F,
+    ) -> Result<()>
+    where
+        F: FnMut(Centimeter) -> bool,
+    {
+        let min_height = self.config.min_table_height_cm;
+        let max_height = self.config.max_table_height_cm;
+        let within_limits = |height: Centimeter| match direction {
+            MoveDirection::Up => height < max_height,
+            MoveDirection::Down => height > min_height,
+        };
+        // The limits cannot be enforced without knowing the height
+        let height = self
+            .sensor
+            .current_height()
+            .context("cannot jog without knowing the height")?;
+        if !within_limits(height) {
+            info!("Not jogging {direction:?}, the table is already at {height:?}");
+            return Ok(());
+        }
+        debug!("Jogging {direction:?} from {height:?}");
+        // Jogging may go on up to the height limit
+        let expected_run_time = self.expected_run_time(
+            direction,
+            match direction {
+                MoveDirection::Up => max_height - height,
+                MoveDirection::Down => height - min_height,
+            },
+        );
+        let mut measurement_error = None;
+        self.feedback.set_status(Status::Moving);
+        let result = self.drive(direction, expected_run_time, |sensor| {
+            match sensor.current_height() {
+                Ok(height) => within_limits(height) && condition(height),
+                Err(e) => {
+                    // Stop if there is an error in the measurement
+                    measurement_error = Some(e);
+                    false
+                }
+            }
+        });
+        if result.is_err() || measurement_error.is_some() {
+            self.feedback.set_status(Status::Error);
+        } else {
+            self.feedback.set_status(Status::Idle);
+        }
+        result?;
+        if let Some(e) = measurement_error {
+            return Err(e.context("height measurement failed while jogging"));
+        }
+        // Jogging has no target height, so only the new height is recorded
+        let height = self.sensor.current_height()?;
+        self.telemetry
+            .record(&TelemetryEvent::HeightSample(HeightSample {
+                measured_at: SystemTime::now(),
+                height,
+            }));
+        Ok(())
+    }
+}
+
+/// The timings of moving the table to an end stop.
+#[derive(Debug, Clone, Copy)]
+struct EndStopTimings {
+    // The time until the table stopped moving
+    travel_time: Duration,
+    // The time until the table started moving, if it moved at all
+    start_latency: Option<Duration>,
+}
+
+impl<S: DistanceSensor, M: MotorDriver> Movement for StandingDesk<S, M> {
+    fn move_to_standing(&mut self) -> Result<()> {
+        info!("Moving to standing position ...");
+        self.move_to_height(self.config.standing_height_cm)
+    }
+
+    fn move_to_sitting(&mut self) -> Result<()> {
+        info!("Moving to standing position ...");
+        self.move_to_height(self.config.sitting_height_cm)
+    }
+
+    fn calibrate(
+        &mut self,
+        temperature_celsius: Option<f32>,
+    ) -> Result<()> {
+        // Move the table until it stops moving, i.e. until the sensor readings
+        // don't change anymore. The assumption is that the table then reached its
+        // physical end stops at the highest and lowest positions.
+        // The highest and lowest positions are defined in the configuration data, and
+        // are calibrated for accordingly.
+
+        info!("Calibrating");
+        if !self.sensor.measures_height() {
+            return Err(anyhow!(
+                "cannot calibrate without a distance sensor, configure the table's travel times \
+                 instead"
+            ));
+        }
+        let previous_calibration_data = self.sensor.calibration_data().cloned();
+        self.feedback.set_status(Status::Moving);
+        if let Err(e) = self.measure_calibration(temperature_celsius) {
+            self.sensor
+                .restore_calibration_data(previous_calibration_data);
+            self.feedback.set_status(Status::Error);
+            self.feedback.beep(Beep::Error);
+            let e = e.context("calibration failed, keeping the previous calibration");
+            self.webhooks
+                .send(&WebhookEvent::CalibrationFinished(CalibrationFinished {
+                    finished_at: SystemTime::now(),
+                    succeeded: false,
+                    error: Some(format!("{e:#}")),
+                }));
+            return Err(e);
+        }
+        self.feedback.set_status(Status::Idle);
+        self.feedback.beep(Beep::CalibrationDone);
+        let calibration_data = self
+            .sensor
+            .calibration_data()
+            .ok_or_else(|| anyhow!("calibration data missing after calibration"))?;
+        CalibrationStore::new(self.sensor.calibration_file()).save(calibration_data)?;
+        if let Some(metadata) = &calibration_data.metadata {
+            METRICS.set_calibrated_at(metadata.calibrated_at);
+        }
+        self.webhooks
+            .send(&WebhookEvent::CalibrationFinished(CalibrationFinished {
+                finished_at: SystemTime::now(),
+                succeeded: true,
+                error: None,
+            }));
+
+        self.move_to_sitting()
+    }
+
+    fn move_to_height(
+        &mut self,
+        height_cm: Centimeter,
+    ) -> Result<()> {
+        if self.trigger.is_automated() && !self.presence.is_present() {
+            info!("Nobody is at the desk, skipping the movement to {height_cm:?}");
+            self.record_skipped(height_cm);
+            return Ok(());
+        }
+        if self.trigger.is_automated()
+            && !self.warning.warn_and_wait(height_cm, &mut self.feedback)?
+        {
+            self.record_skipped(height_cm);
+            return Ok(());
+        }
+        let started_at = SystemTime::now();
+        let from_height = self.sensor.current_height().ok();
+        self.webhooks
+            .send(&WebhookEvent::MovementStarted(MovementStarted {
+                started_at,
+                from_height,
+                target_height: height_cm,
+                trigger: self.trigger,
+            }));
+        self.feedback.set_status(Status::Moving);
+        self.feedback.beep(Beep::MovementStarted);
+        let result = self.move_to_height_untracked(height_cm);
+        let to_height = self.sensor.current_height().ok();
+        let outcome = match (&result, to_height) {
+            (Err(_), _) => MovementOutcome::Failed,
+            (Ok(()), Some(to_height))
+                if height_cm - HEIGHT_TOLERANCE <= to_height
+                    && to_height <= height_cm + HEIGHT_TOLERANCE =>
+            {
+                MovementOutcome::Reached
+            }
+            (Ok(()), _) => MovementOutcome::Missed,
+        };
+        if outcome == MovementOutcome::Reached {
+            self.feedback.set_status(Status::Idle);
+            self.feedback.beep(Beep::Arrived);
+        } else {
+            // Also when the table stopped short of the target, e.g. because the
+            // movement was halted or the motor timed out
+            self.feedback.set_status(Status::Error);
+            self.feedback.beep(Beep::Error);
+        }
+        METRICS.count_movement(outcome);
+        let record = MovementRecord {
+            started_at,
+            ended_at: SystemTime::now(),
+            from_height,
+            target_height: height_cm,
+            to_height,
+            outcome,
+            trigger: self.trigger,
+            error: result.as_ref().err().map(|e| format!("{e:#}")),
+        };
+        self.telemetry
+            .record(&TelemetryEvent::Movement(record.clone()));
+        self.webhooks.send(&match outcome {
+            MovementOutcome::Failed => WebhookEvent::MovementFailed(record),
+            MovementOutcome::Reached | MovementOutcome::Missed => {
+                WebhookEvent::MovementCompleted(record)
+            }
+            MovementOutcome::Skipped => WebhookEvent::MovementSkipped(record),
+        });
+        result
+    }
+
+    fn move_by(
+        &mut self,
+        direction: MoveDirection,
+        distance_cm: Centimeter,
+    ) -> Result<()> {
+        let height = self
+            .sensor
+            .current_height()
+            .context("cannot move by a distance without knowing the height")?;
+        let target_height = match direction {
+            MoveDirection::Up => height.into_inner().saturating_add(distance_cm.into_inner()),
+            MoveDirection::Down => height.into_inner().saturating_sub(distance_cm.into_inner()),
+        };
+        let target_height = Centimeter(target_height).clamp(
+            self.config.min_table_height_cm,
+            self.config.max_table_height_cm,
+        );
+        info!("Moving {direction:?} by {distance_cm:?} from {height:?} to {target_height:?}");
+        if distance_cm <= HEIGHT_TOLERANCE {
+            // The table counts as already being at a target this close, so
+            // nudge it until the height changed instead
+            return self.jog_while(direction, |height| match direction {
+                MoveDirection::Up => height < target_height,
+                MoveDirection::Down => height > target_height,
+            });
+        }
+        self.move_to_height(target_height)
+    }
+
+    fn jog<F>(
+        &mut self,
+        direction: MoveDirection,
+        mut condition: F,
+    ) -> Result<()>
+    where
+        F: FnMut() -> bool,
+    {
+        self.jog_while(direction, |_| condition())
+    }
+
+    fn abort_when(
+        &mut self,
+        abort: fn() -> bool,
+    ) {
+        self.abort = Some(abort);
+    }
+
+    fn current_height(&mut self) -> Result<Centimeter> {
+        self.sensor.current_height()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+    use crate::config::FeedbackConfig;
+    use crate::config::PresenceConfig;
+    use crate::config::TelemetryConfig;
+    use crate::config::WarningConfig;
+    use crate::config::WebhooksConfig;
+    use crate::sensor::SensorCalibrationData;
+
+    /// A sensor without measurements, recording the end stops it is told
+    /// about.
+    #[derive(Debug, Default)]
+    struct EndStopSensor {
+        reached: Option<Centimeter>,
+        missed: bool,
+    }
+
+    impl DistanceSensor for EndStopSensor {
+        fn current_height(&mut self) -> Result<Centimeter> {
+            Err(anyhow!("there is no distance sensor"))
+        }
+
+        fn set_min_height(
+            &mut self,
+            _height: Centimeter,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        fn set_max_height(
+            &mut self,
+            _height: Centimeter,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        fn echo_duration(&mut self) -> Result<Duration> {
+            Err(anyhow!("there is no distance sensor"))
+        }
+
+        fn calibration_samples(&self) -> u8 {
+            0
+        }
+
+        fn restore_calibration_data(
+            &mut self,
+            _calibration_data: Option<SensorCalibrationData>,
+        ) {
+        }
+
+        fn calibration_file(&self) -> &Path {
+            Path::new("")
+        }
+
+        fn calibration_data(&self) -> Option<&SensorCalibrationData> {
+            None
+        }
+
+        fn calibration_data_mut(&mut self) -> Option<&mut SensorCalibrationData> {
+            None
+        }
+
+        fn end_stop_reached(
+            &mut self,
+            height: Centimeter,
+        ) {
+            self.reached = Some(height);
+        }
+
+        fn end_stop_missed(&mut self) {
+            self.missed = true;
+        }
+    }
+
+    /// A motor which runs like the real one, but without moving anything.
+    #[derive(Debug)]
+    struct IdleMotor {
+        shutdown: Shutdown,
+    }
+
+    impl IdleMotor {
+        fn run<F>(
+            &mut self,
+            condition: &mut F,
+        ) -> Result<()>
+        where
+            F: FnMut() -> bool,
+        {
+            while condition() && !self.shutdown.is_halted() {
+                sleep(Duration::from_millis(1));
+            }
+            Ok(())
+        }
+    }
+
+    impl MotorDriver for IdleMotor {
+        fn up_until_false_or_timeout<F>(
+            &mut self,
+            _expected_run_time: Option<Duration>,
+            condition: &mut F,
+        ) -> Result<()>
+        where
+            F: FnMut() -> bool,
+        {
+            self.run(condition)
+        }
+
+        fn down_until_false_or_timeout<F>(
+            &mut self,
+            _expected_run_time: Option<Duration>,
+            condition: &mut F,
+        ) -> Result<()>
+        where
+            F: FnMut() -> bool,
+        {
+            self.run(condition)
+        }
+    }
+
+    fn desk(shutdown: Shutdown) -> StandingDesk<EndStopSensor, IdleMotor> {
+        StandingDesk {
+            config: TableConfig {
+                max_table_height_cm: Centimeter(120),
+                min_table_height_cm: Centimeter(70),
+                sitting_height_cm: Centimeter(75),
+                standing_height_cm: Centimeter(110),
+            },
+            sensor: EndStopSensor::default(),
+            motor_driver: IdleMotor {
+                shutdown: shutdown.clone(),
+            },
+            telemetry: Telemetry::new(&TelemetryConfig {
+                enabled: false,
+                ..Default::default()
+            }),
+            trigger: Trigger::Cli,
+            presence: Presence::new(&PresenceConfig::default()),
+            warning: MoveWarning::new(&WarningConfig::default(), shutdown.clone()),
+            feedback: Feedback::new(&FeedbackConfig::default()),
+            webhooks: Webhooks::new(&WebhooksConfig::default()),
+            shutdown,
+            abort: None,
+        }
+    }
+
+    fn motion() -> MotionCalibration {
+        MotionCalibration {
+            up_travel_secs: 0.02,
+            down_travel_secs: 0.02,
+            up_speed_cm_per_sec: 2500.0,
+            down_speed_cm_per_sec: 2500.0,
+            start_latency_secs: 0.0,
+            stop_latency_secs: 0.0,
+        }
+    }
+
+    #[test]
+    fn reports_the_end_stop_after_moving_for_the_whole_travel_time() {
+        let mut desk = desk(Shutdown::default());
+
+        desk.move_to_end_stop_blindly(MoveDirection::Down, &motion())
+            .unwrap();
+
+        assert_eq!(desk.sensor.reached, Some(Centimeter(70)));
+        assert!(!desk.sensor.missed);
+    }
+
+    #[test]
+    fn leaves_the_position_unknown_when_halted_before_the_end_stop() {
+        let shutdown = Shutdown::default();
+        shutdown.halt();
+        let mut desk = desk(shutdown);
+
+        assert!(desk
+            .move_to_end_stop_blindly(MoveDirection::Down, &motion())
+            .is_err());
+
+        assert_eq!(desk.sensor.reached, None);
+        assert!(desk.sensor.missed);
+    }
 }
@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::IsoWeek;
+use chrono::Local;
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use log::debug;
+use log::warn;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::config::TelemetryConfig;
+use crate::primitives::Centimeter;
+
+/// What caused a movement of the table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Trigger {
+    /// A command issued manually on the command line.
+    Cli,
+    /// A scheduled command, e.g. from a cronjob.
+    Schedule,
+    /// A reminder because the daily standing goal is behind schedule.
+    #[value(skip)]
+    Reminder,
+    /// A physical button wired to the computer.
+    #[value(skip)]
+    Button,
+}
+
+/// How a movement ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MovementOutcome {
+    /// The table reached the desired height.
+    Reached,
+    /// The table stopped before reaching the desired height, e.g. because the
+    /// motor timed out or the movement was interrupted.
+    Missed,
+    /// The movement failed with an error.
+    Failed,
+    /// The automated movement did not happen because nobody was at the desk
+    /// or it was snoozed or cancelled.
+    Skipped,
+}
+
+/// A recorded movement of the table.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub(crate) struct MovementRecord {
+    #[serde(with = "crate::storage::rfc3339")]
+    pub started_at: SystemTime,
+    #[serde(with = "crate::storage::rfc3339")]
+    pub ended_at: SystemTime,
+    // The height before the movement, if it could be measured
+    pub from_height: Option<Centimeter>,
+    // The height the table should have been moved to
+    pub target_height: Centimeter,
+    // The height after the movement, if it could be measured
+    pub to_height: Option<Centimeter>,
+    pub outcome: MovementOutcome,
+    pub trigger: Trigger,
+    // The error the movement failed with, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A recorded height measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub(crate) struct HeightSample {
+    #[serde(with = "crate::storage::rfc3339")]
+    pub measured_at: SystemTime,
+    pub height: Centimeter,
+}
+
+/// An entry in the telemetry log.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum TelemetryEvent {
+    Movement(MovementRecord),
+    HeightSample(HeightSample),
+}
+
+/// Records telemetry events in an append-only log with one JSON object per
+/// line.
+#[derive(Debug, Clone)]
+pub(crate) struct Telemetry {
+    // No events are recorded if telemetry is disabled
+    log_file: Option<PathBuf>,
+}
+
+impl Telemetry {
+    /// Creates a new [Telemetry] instance with the provided configuration.
+    pub(crate) fn new(config: &TelemetryConfig) -> Self {
+        Self {
+            log_file: config.enabled.then(|| config.log_file.clone()),
+        }
+    }
+
+    /// Appends an event to the log.
+    ///
+    /// Failing to record an event is logged but not treated as an error as
+    /// telemetry must not get in the way of moving the table.
+    pub(crate) fn record(
+        &self,
+        event: &TelemetryEvent,
+    ) {
+        let Some(log_file) = &self.log_file else {
+            return;
+        };
+        let result = serde_json::to_string(event)
+            .map_err(anyhow::Error::from)
+            .and_then(|mut line| {
+                line.push('\n');
+                if let Some(dir) = log_file.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                // A single write of a whole line keeps the log intact even if
+                // several processes append to it.
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(log_file)?
+                    .write_all(line.as_bytes())?;
+                Ok(())
+            });
+        match result {
+            Ok(()) => debug!("Recorded telemetry event {event:?}"),
+            Err(e) => warn!("Could not record telemetry event in {log_file:?}: {e}"),
+        }
+    }
+
+    /// Reads all events from the log, oldest first.
+    ///
+    /// Malformed lines, e.g. from an interrupted write, are skipped.
+    pub(crate) fn events(&self) -> Result<Vec<TelemetryEvent>> {
+        let Some(log_file) = &self.log_file else {
+            return Ok(vec![]);
+        };
+        let raw_events = match fs::read_to_string(log_file) {
+            Ok(raw_events) => raw_events,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut events: Vec<TelemetryEvent> = raw_events
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(event) => Some(event),
+                Err(e) => {
+                    warn!("Skipping malformed telemetry event {line:?}: {e}");
+                    None
+                }
+            })
+            .collect();
+        events.sort_by_key(TelemetryEvent::time);
+        Ok(events)
+    }
+}
+
+impl Trigger {
+    /// All triggers.
+    pub(crate) const ALL: [Trigger; 4] = [
+        Trigger::Cli,
+        Trigger::Schedule,
+        Trigger::Reminder,
+        Trigger::Button,
+    ];
+
+    /// The name of the trigger as recorded in the telemetry log.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Trigger::Cli => "cli",
+            Trigger::Schedule => "schedule",
+            Trigger::Reminder => "reminder",
+            Trigger::Button => "button",
+        }
+    }
+
+    /// Whether the movement happens without anybody asking for it right now.
+    pub(crate) fn is_automated(self) -> bool {
+        matches!(self, Trigger::Schedule | Trigger::Reminder)
+    }
+}
+
+impl TelemetryEvent {
+    /// The time the event happened at.
+    pub(crate) fn time(&self) -> SystemTime {
+        match self {
+            TelemetryEvent::Movement(movement) => movement.ended_at,
+            TelemetryEvent::HeightSample(sample) => sample.measured_at,
+        }
+    }
+
+    /// The height of the table after the event, if known.
+    fn height(&self) -> Option<Centimeter> {
+        match self {
+            TelemetryEvent::Movement(movement) => movement.to_height,
+            TelemetryEvent::HeightSample(sample) => Some(sample.height),
+        }
+    }
+}
+
+/// The time spent sitting and standing in some period.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PostureSummary {
+    pub sitting: Duration,
+    pub standing: Duration,
+    pub movements: u32,
+}
+
+impl PostureSummary {
+    fn add(
+        &mut self,
+        other: &PostureSummary,
+    ) {
+        self.sitting += other.sitting;
+        self.standing += other.standing;
+        self.movements += other.movements;
+    }
+}
+
+/// A period during which the table stayed in the sitting or standing
+/// position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PostureInterval {
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub standing: bool,
+}
+
+/// Reconstructs the periods spent sitting and standing from the events.
+///
+/// The table is assumed to stay at the height of an event until the next
+/// event, or until `now` for the last one, but for no longer than `max_gap`.
+/// Heights from `standing_threshold` upwards count as standing.
+pub(crate) fn posture_intervals(
+    events: &[TelemetryEvent],
+    standing_threshold: Centimeter,
+    max_gap: Duration,
+    now: SystemTime,
+) -> Vec<PostureInterval> {
+    let timeline: Vec<(SystemTime, Centimeter)> = events
+        .iter()
+        .filter_map(|event| Some((event.time(), event.height()?)))
+        .collect();
+    let next_times = timeline.iter().skip(1).map(|(time, _)| *time).chain([now]);
+    timeline
+        .iter()
+        .zip(next_times)
+        .map(|(&(start, height), next_time)| {
+            let gap = next_time.duration_since(start).unwrap_or_default();
+            PostureInterval {
+                start,
+                end: start + gap.min(max_gap),
+                standing: height >= standing_threshold,
+            }
+        })
+        .collect()
+}
+
+/// Summarises the time spent sitting and standing per local day.
+///
+/// See [`posture_intervals`] for how the time is attributed.
+pub(crate) fn summarise_by_day(
+    events: &[TelemetryEvent],
+    standing_threshold: Centimeter,
+    max_gap: Duration,
+    now: SystemTime,
+) -> BTreeMap<NaiveDate, PostureSummary> {
+    let mut summaries: BTreeMap<NaiveDate, PostureSummary> = BTreeMap::new();
+    for event in events {
+        if let TelemetryEvent::Movement(movement) = event {
+            if movement.outcome == MovementOutcome::Skipped {
+                continue;
+            }
+            summaries
+                .entry(local_date(movement.started_at))
+                .or_default()
+                .movements += 1;
+        }
+    }
+    for interval in posture_intervals(events, standing_threshold, max_gap, now) {
+        for (date, duration) in split_by_local_day(interval.start, interval.end) {
+            let summary = summaries.entry(date).or_default();
+            if interval.standing {
+                summary.standing += duration;
+            } else {
+                summary.sitting += duration;
+            }
+        }
+    }
+    summaries
+}
+
+/// Aggregates daily summaries into ISO weeks.
+pub(crate) fn summarise_by_week(
+    daily_summaries: &BTreeMap<NaiveDate, PostureSummary>
+) -> BTreeMap<IsoWeek, PostureSummary> {
+    let mut summaries: BTreeMap<IsoWeek, PostureSummary> = BTreeMap::new();
+    for (date, daily_summary) in daily_summaries {
+        summaries
+            .entry(date.iso_week())
+            .or_default()
+            .add(daily_summary);
+    }
+    summaries
+}
+
+/// Formats a duration as hours and minutes, e.g. `2h 05m`.
+pub(crate) fn format_hours_minutes(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    format!("{}h {:02}m", minutes / 60, minutes % 60)
+}
+
+/// The local date at the given time.
+pub(crate) fn local_date(time: SystemTime) -> NaiveDate {
+    DateTime::<Local>::from(time).date_naive()
+}
+
+/// Splits the period between `start` and `end` at local midnights.
+pub(crate) fn split_by_local_day(
+    start: SystemTime,
+    end: SystemTime,
+) -> Vec<(NaiveDate, Duration)> {
+    let mut periods = vec![];
+    let mut period_start = start;
+    while period_start < end {
+        let date = local_date(period_start);
+        let next_midnight = date
+            .succ_opt()
+            .and_then(|next_date| next_date.and_hms_opt(0, 0, 0))
+            .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+            .map(SystemTime::from)
+            .unwrap_or(end);
+        // Guard against not making progress around time zone changes
+        let period_end = next_midnight
+            .max(period_start + Duration::from_secs(1))
+            .min(end);
+        periods.push((
+            date,
+            period_end.duration_since(period_start).unwrap_or_default(),
+        ));
+        period_start = period_end;
+    }
+    periods
+}
@@ -0,0 +1,343 @@
+use std::io::stdout;
+use std::io::Stdout;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use crossterm::cursor;
+use crossterm::event;
+use crossterm::event::Event;
+use crossterm::event::KeyCode;
+use crossterm::event::KeyEvent;
+use crossterm::event::KeyEventKind;
+use crossterm::event::KeyModifiers;
+use crossterm::execute;
+use crossterm::queue;
+use crossterm::style::Print;
+use crossterm::terminal;
+use crossterm::terminal::ClearType;
+
+use crate::config::Config;
+use crate::config::TableConfig;
+use crate::motor::MoveDirection;
+use crate::movement::Movement;
+use crate::presets;
+use crate::primitives::Centimeter;
+use crate::shutdown::Shutdown;
+
+// How often the height is measured while idle
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+// How long a single key press jogs the table for
+const JOG_DURATION: Duration = Duration::from_millis(250);
+// The width of the bar showing the position between the lowest and highest
+// heights
+const BAR_WIDTH: usize = 40;
+
+/// An interactive terminal UI for controlling the table, e.g. while setting
+/// up a new desk.
+#[derive(Debug)]
+pub(crate) struct Tui {
+    limits: TableConfig,
+    // The presets which can be jumped to with the number keys
+    presets: Vec<(String, Centimeter)>,
+    presets_file: PathBuf,
+    // The most recent measurement
+    height: Option<Result<Centimeter, String>>,
+    // The outcome of the last action
+    status: String,
+    mode: Mode,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Mode {
+    Control,
+    // Entering the name of a preset to save the current height as
+    SavePreset(String),
+    // Waiting for the calibration to be confirmed
+    ConfirmCalibration,
+}
+
+/// Restores the terminal when dropped, even if the UI fails.
+struct TerminalGuard {
+    out: Stdout,
+}
+
+impl TerminalGuard {
+    fn enter() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut out = stdout();
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(Self { out })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(self.out, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+impl Tui {
+    /// Creates a new [Tui] for the table and presets in the configuration.
+    pub(crate) fn new(config: &Config) -> Self {
+        Self {
+            limits: config.table,
+            presets: config.all_presets(),
+            presets_file: config.presets_file.clone(),
+            height: None,
+            status: String::new(),
+            mode: Mode::Control,
+        }
+    }
+
+    /// Runs the UI until it is quit or a shutdown is requested.
+    ///
+    /// Movements and the calibration are stopped with Esc or Ctrl-C.
+    pub(crate) fn run<T: Movement>(
+        &mut self,
+        table: &mut T,
+        shutdown: &Shutdown,
+    ) -> Result<()> {
+        let mut terminal = TerminalGuard::enter()?;
+        table.abort_when(stop_key_pressed);
+        let mut last_refresh: Option<Instant> = None;
+        while !shutdown.is_requested() {
+            if last_refresh.is_none_or(|last_refresh| last_refresh.elapsed() >= REFRESH_INTERVAL) {
+                self.measure(table);
+                last_refresh = Some(Instant::now());
+            }
+            self.draw(&mut terminal.out)?;
+            if !event::poll(REFRESH_INTERVAL)? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            // A stop only applies to the movement running at the time
+            shutdown.resume();
+            if !self.handle_key(key, table, shutdown, &mut terminal.out)? {
+                break;
+            }
+            self.measure(table);
+            last_refresh = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Handles a key press, returning whether the UI should keep running.
+    fn handle_key<T: Movement>(
+        &mut self,
+        key: KeyEvent,
+        table: &mut T,
+        shutdown: &Shutdown,
+        out: &mut Stdout,
+    ) -> Result<bool> {
+        if is_ctrl_c(key) {
+            return Ok(false);
+        }
+        match (&mut self.mode, key.code) {
+            (Mode::Control, KeyCode::Char('q') | KeyCode::Esc) => return Ok(false),
+            (Mode::Control, KeyCode::Up | KeyCode::Char('k')) => self.jog(table, MoveDirection::Up),
+            (Mode::Control, KeyCode::Down | KeyCode::Char('j')) => {
+                self.jog(table, MoveDirection::Down)
+            }
+            (Mode::Control, KeyCode::Char(digit @ '1'..='9')) => {
+                let index = digit as usize - '1' as usize;
+                match self.presets.get(index).cloned() {
+                    Some((name, height)) => {
+                        self.status = format!(
+                            "Moving to {name} ({}cm), esc to stop ...",
+                            height.into_inner()
+                        );
+                        self.draw(out)?;
+                        let result = table.move_to_height(height);
+                        self.status = if shutdown.is_halted() {
+                            format!("Stopped moving to {name}")
+                        } else {
+                            match result {
+                                Ok(()) => format!("Moved to {name}"),
+                                Err(e) => format!("Moving to {name} failed: {e:#}"),
+                            }
+                        };
+                    }
+                    None => self.status = format!("There is no preset {digit}"),
+                }
+            }
+            (Mode::Control, KeyCode::Char('s')) => self.mode = Mode::SavePreset(String::new()),
+            (Mode::Control, KeyCode::Char('c')) => self.mode = Mode::ConfirmCalibration,
+            (Mode::SavePreset(name), KeyCode::Char(c)) if !c.is_whitespace() => name.push(c),
+            (Mode::SavePreset(name), KeyCode::Backspace) => {
+                name.pop();
+            }
+            (Mode::SavePreset(name), KeyCode::Enter) => {
+                let name = name.clone();
+                self.mode = Mode::Control;
+                self.status = match self.save_preset(&name) {
+                    Ok(height) => format!("Saved {name} at {}cm", height.into_inner()),
+                    Err(e) => format!("Saving the preset failed: {e:#}"),
+                };
+            }
+            (Mode::ConfirmCalibration, KeyCode::Char('y')) => {
+                self.mode = Mode::Control;
+                self.status = "Calibrating, this takes a while, esc to stop ...".to_string();
+                self.draw(out)?;
+                let result = table.calibrate(None);
+                self.status = if shutdown.is_halted() {
+                    "Stopped calibrating, keeping the previous calibration".to_string()
+                } else {
+                    match result {
+                        Ok(()) => "Calibrated".to_string(),
+                        Err(e) => format!("{e:#}"),
+                    }
+                };
+            }
+            (Mode::SavePreset(_) | Mode::ConfirmCalibration, KeyCode::Esc) => {
+                self.mode = Mode::Control;
+            }
+            (Mode::ConfirmCalibration, _) => self.mode = Mode::Control,
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn measure<T: Movement>(
+        &mut self,
+        table: &mut T,
+    ) {
+        self.height = Some(table.current_height().map_err(|e| format!("{e:#}")));
+    }
+
+    fn jog<T: Movement>(
+        &mut self,
+        table: &mut T,
+        direction: MoveDirection,
+    ) {
+        let start = Instant::now();
+        if let Err(e) = table.jog(direction, || start.elapsed() < JOG_DURATION) {
+            self.status = format!("Jogging {direction:?} failed: {e:#}");
+        }
+        // Drop the key presses repeated while jogging so that the table does
+        // not keep moving after the key is released
+        while let Ok(true) = event::poll(Duration::ZERO) {
+            if event::read().is_err() {
+                break;
+            }
+        }
+    }
+
+    fn save_preset(
+        &mut self,
+        name: &str,
+    ) -> Result<Centimeter> {
+        if name.is_empty() {
+            return Err(anyhow!("the name is empty"));
+        }
+        if matches!(name, "sitting" | "standing") {
+            return Err(anyhow!("the {name} height is set in the config"));
+        }
+        let height = match &self.height {
+            Some(Ok(height)) => *height,
+            _ => return Err(anyhow!("the height is unknown")),
+        };
+        presets::save(&self.presets_file, name, height)?;
+        self.presets.retain(|(preset_name, _)| preset_name != name);
+        self.presets.push((name.to_string(), height));
+        self.presets.sort_by_key(|(_, height)| *height);
+        Ok(height)
+    }
+
+    fn draw(
+        &self,
+        out: &mut Stdout,
+    ) -> Result<()> {
+        let min_height = self.limits.min_table_height_cm.into_inner();
+        let max_height = self.limits.max_table_height_cm.into_inner();
+        let height_line = match &self.height {
+            Some(Ok(height)) => format!("Height: {}cm", height.into_inner()),
+            Some(Err(e)) => format!("Height: unknown ({e})"),
+            None => "Height: unknown".to_string(),
+        };
+        let filled = match &self.height {
+            Some(Ok(height)) if max_height > min_height => {
+                let position = height.into_inner().clamp(min_height, max_height) - min_height;
+                position as usize * BAR_WIDTH / (max_height - min_height) as usize
+            }
+            _ => 0,
+        };
+        let bar_line = format!(
+            "{min_height}cm [{}{}] {max_height}cm",
+            "#".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled)
+        );
+        let presets_line = self
+            .presets
+            .iter()
+            .take(9)
+            .enumerate()
+            .map(|(index, (name, height))| {
+                format!("{} {name} ({}cm)", index + 1, height.into_inner())
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        let help_line = match &self.mode {
+            Mode::Control => {
+                "up/k: jog up  down/j: jog down  1-9: preset  s: save preset  c: calibrate  q: quit"
+                    .to_string()
+            }
+            Mode::SavePreset(name) => {
+                format!("Save the current height as preset: {name}_  (enter: save, esc: cancel)")
+            }
+            Mode::ConfirmCalibration => {
+                "Calibrate now? The desk moves all the way down and up. (y: yes, any other key: no)"
+                    .to_string()
+            }
+        };
+        queue!(out, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+        for line in [
+            "Standup",
+            "",
+            &height_line,
+            &bar_line,
+            "",
+            &format!("Presets: {presets_line}"),
+            "",
+            &help_line,
+            &self.status,
+        ] {
+            queue!(out, Print(line), Print("\r\n"))?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Whether Esc or Ctrl-C has been pressed to stop the current movement. Other
+/// key presses are dropped so that they don't take effect after the movement.
+fn stop_key_pressed() -> bool {
+    while let Ok(true) = event::poll(Duration::ZERO) {
+        match event::read() {
+            Ok(Event::Key(key))
+                if key.kind == KeyEventKind::Press
+                    && (key.code == KeyCode::Esc || is_ctrl_c(key)) =>
+            {
+                return true;
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    false
+}
+
+/// Whether the key is Ctrl-C, which arrives as a key press as the terminal is
+/// in raw mode.
+fn is_ctrl_c(key: KeyEvent) -> bool {
+    key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c')
+}
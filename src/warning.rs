@@ -0,0 +1,188 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread::sleep;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use log::info;
+use log::warn;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::config::WarningConfig;
+use crate::feedback::Beep;
+use crate::feedback::Feedback;
+use crate::primitives::Centimeter;
+use crate::shutdown::Shutdown;
+use crate::storage::write_atomically;
+use crate::systemd;
+
+// How often the snooze file is checked during the warning period
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+// The longest time automated movements can be snoozed for
+const MAX_SNOOZE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+/// The longest time in minutes automated movements can be snoozed for.
+pub(crate) const MAX_SNOOZE_MINS: u64 = MAX_SNOOZE.as_secs() / 60;
+
+/// Warns before automated movements and gives a chance to cancel or snooze
+/// them with `standup cancel` and `standup snooze` from another process.
+#[derive(Debug)]
+pub(crate) struct MoveWarning {
+    delay: Duration,
+    notify_command: Option<String>,
+    snooze_file: PathBuf,
+    shutdown: Shutdown,
+}
+
+/// A request to skip automated movements, persisted in the snooze file.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+struct Snooze {
+    // When the request was made. Movements warned about before this are
+    // cancelled, so it must not be truncated to seconds.
+    #[serde(with = "crate::storage::rfc3339_nanos")]
+    requested_at: SystemTime,
+    // Automated movements are skipped until then
+    #[serde(with = "crate::storage::rfc3339")]
+    until: SystemTime,
+}
+
+impl MoveWarning {
+    /// Creates a new [`MoveWarning`].
+    pub(crate) fn new(
+        config: &WarningConfig,
+        shutdown: Shutdown,
+    ) -> Self {
+        Self {
+            delay: Duration::from_secs(config.delay_secs),
+            notify_command: config.notify_command.clone(),
+            snooze_file: config.snooze_file.clone(),
+            shutdown,
+        }
+    }
+
+    /// Warns that the table is about to move to `height_cm`, beeping the
+    /// buzzer, and waits for the warning period. Returns whether the movement
+    /// should go ahead, i.e. it has not been snoozed before or cancelled
+    /// during the warning period.
+    ///
+    /// # Errors
+    /// Errors if a shutdown is requested or the movement is halted during the
+    /// warning period.
+    pub(crate) fn warn_and_wait(
+        &mut self,
+        height_cm: Centimeter,
+        feedback: &mut Feedback,
+    ) -> Result<bool> {
+        let warned_at = SystemTime::now();
+        if let Some(snooze) = self.snooze() {
+            if snooze.until > warned_at {
+                info!(
+                    "Automated movements are snoozed until {}, skipping the movement to \
+                     {height_cm:?}",
+                    humantime::format_rfc3339_seconds(snooze.until)
+                );
+                return Ok(false);
+            }
+        }
+        if self.delay.is_zero() {
+            return Ok(true);
+        }
+        warn!(
+            "Moving to {}cm in {}s, run `standup cancel` or `standup snooze` to skip the movement",
+            height_cm.into_inner(),
+            self.delay.as_secs()
+        );
+        self.notify(height_cm);
+        feedback.beep(Beep::Warning);
+        let start = Instant::now();
+        while start.elapsed() < self.delay {
+            if self.shutdown.is_halted() {
+                return Err(anyhow!(
+                    "interrupted while warning about the movement to {height_cm:?}"
+                ));
+            }
+            if self
+                .snooze()
+                .is_some_and(|snooze| snooze.requested_at >= warned_at)
+            {
+                info!("The movement to {height_cm:?} has been cancelled");
+                return Ok(false);
+            }
+            systemd::ping_watchdog();
+            sleep(POLL_INTERVAL);
+        }
+        Ok(true)
+    }
+
+    /// Runs the notification command, if any, with the target height and the
+    /// warning period in the environment. Failures are only logged so that a
+    /// broken notification does not keep the table from moving.
+    fn notify(
+        &self,
+        height_cm: Centimeter,
+    ) {
+        let Some(command) = &self.notify_command else {
+            return;
+        };
+        let result = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("STANDUP_TARGET_CM", height_cm.into_inner().to_string())
+            .env("STANDUP_DELAY_SECS", self.delay.as_secs().to_string())
+            .spawn();
+        if let Err(e) = result {
+            warn!("Could not run the notification command: {e}");
+        }
+    }
+
+    /// The latest snooze request. A snooze file which cannot be read is
+    /// ignored with a warning.
+    fn snooze(&self) -> Option<Snooze> {
+        load_snooze(&self.snooze_file).unwrap_or_else(|e| {
+            warn!("Could not read the snooze file: {e:#}");
+            None
+        })
+    }
+}
+
+/// Skips automated movements for `duration`, including any movement currently
+/// being warned about. A zero duration only cancels pending movements. An
+/// earlier snooze lasting longer is kept.
+///
+/// # Errors
+/// Errors if `duration` is longer than [`MAX_SNOOZE_MINS`] or the snooze file
+/// cannot be written.
+pub(crate) fn snooze(
+    config: &WarningConfig,
+    duration: Duration,
+) -> Result<()> {
+    if duration > MAX_SNOOZE {
+        return Err(anyhow!(
+            "cannot snooze for longer than {MAX_SNOOZE_MINS} minutes"
+        ));
+    }
+    let now = SystemTime::now();
+    let earlier_until = load_snooze(&config.snooze_file)
+        .ok()
+        .flatten()
+        .map(|snooze| snooze.until);
+    let snooze = Snooze {
+        requested_at: now,
+        until: earlier_until.map_or(now + duration, |until| until.max(now + duration)),
+    };
+    write_atomically(&config.snooze_file, toml::to_string(&snooze)?.as_bytes())
+}
+
+fn load_snooze(path: &Path) -> Result<Option<Snooze>> {
+    match fs::read_to_string(path) {
+        Ok(raw_snooze) => Ok(Some(toml::from_str(&raw_snooze)?)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
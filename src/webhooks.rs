@@ -0,0 +1,530 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::thread::sleep;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use log::debug;
+use log::warn;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::config::WebhooksConfig;
+use crate::primitives::Centimeter;
+use crate::storage::write_atomically;
+use crate::telemetry::MovementRecord;
+use crate::telemetry::Trigger;
+
+// The oldest webhooks are dropped once more than this many are queued, so
+// that an unreachable endpoint doesn't fill up the disk
+const MAX_QUEUED: usize = 1000;
+// The longest delay between two attempts to deliver a webhook
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+// How long exiting waits for the queued webhooks to be delivered, the rest
+// stays queued for later
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+// How often exiting checks whether the queued webhooks were delivered
+const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// An event posted to the webhooks as a JSON object.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum WebhookEvent {
+    MovementStarted(MovementStarted),
+    // The movement ended without an error, but not necessarily at the target
+    // height
+    MovementCompleted(MovementRecord),
+    MovementFailed(MovementRecord),
+    // An automated movement which did not happen
+    MovementSkipped(MovementRecord),
+    CalibrationFinished(CalibrationFinished),
+}
+
+/// A movement which is about to start.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct MovementStarted {
+    #[serde(with = "crate::storage::rfc3339")]
+    pub started_at: SystemTime,
+    // The height before the movement, if it could be measured
+    pub from_height: Option<Centimeter>,
+    pub target_height: Centimeter,
+    pub trigger: Trigger,
+}
+
+/// A finished calibration, whether it succeeded or not.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct CalibrationFinished {
+    #[serde(with = "crate::storage::rfc3339")]
+    pub finished_at: SystemTime,
+    pub succeeded: bool,
+    // The error the calibration failed with, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A webhook waiting to be delivered, persisted in the queue file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct QueuedWebhook {
+    id: u128,
+    url: String,
+    body: String,
+}
+
+/// Posts events to the configured URLs.
+///
+/// Events are first appended to a queue file and then delivered in the
+/// background, retrying with exponential backoff. Webhooks which still
+/// cannot be delivered stay queued and are retried with the next event, also
+/// by later invocations.
+#[derive(Debug)]
+pub(crate) struct Webhooks {
+    urls: Vec<String>,
+    queue: Arc<Queue>,
+    // Wakes up the worker delivering the queued webhooks
+    notify: Option<Sender<()>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+struct Queue {
+    file: PathBuf,
+    // Guards the queue file against concurrent changes by the worker
+    lock: Mutex<()>,
+    // The oldest webhooks are dropped once more than this many are queued
+    max_queued: usize,
+}
+
+/// How webhooks are delivered.
+#[derive(Debug, Clone, Copy)]
+struct Delivery {
+    timeout: Duration,
+    max_attempts: u32,
+    retry_delay: Duration,
+}
+
+impl Webhooks {
+    /// Creates a new [Webhooks] instance, starting to deliver any webhooks
+    /// left in the queue by earlier invocations.
+    ///
+    /// # Panics
+    /// Panics if the delivery worker cannot be started.
+    pub(crate) fn new(config: &WebhooksConfig) -> Self {
+        let queue = Arc::new(Queue {
+            file: config.queue_file.clone(),
+            lock: Mutex::new(()),
+            max_queued: MAX_QUEUED,
+        });
+        if config.urls.is_empty() {
+            return Self {
+                urls: vec![],
+                queue,
+                notify: None,
+                worker: None,
+            };
+        }
+        let delivery = Delivery {
+            timeout: Duration::from_secs(config.timeout_secs),
+            max_attempts: config.max_attempts.max(1),
+            retry_delay: Duration::from_millis(config.retry_delay_ms),
+        };
+        let (notify, notifications) = mpsc::channel::<()>();
+        let worker_queue = Arc::clone(&queue);
+        let worker = thread::Builder::new()
+            .name("webhooks".to_string())
+            .spawn(move || {
+                // Deliver what was left over first, then whenever an event is
+                // queued. The loop ends once the sender is dropped.
+                loop {
+                    worker_queue.deliver(delivery);
+                    if notifications.recv().is_err() {
+                        break;
+                    }
+                    // Deliver events queued in quick succession in one go
+                    while notifications.try_recv().is_ok() {}
+                }
+            })
+            .expect("be able to start the webhooks worker");
+        Self {
+            urls: config.urls.clone(),
+            queue,
+            notify: Some(notify),
+            worker: Some(worker),
+        }
+    }
+
+    /// Queues the event for all URLs and wakes up the delivery in the
+    /// background. Failing to queue an event is only logged as webhooks must
+    /// not get in the way of moving the table.
+    pub(crate) fn send(
+        &self,
+        event: &WebhookEvent,
+    ) {
+        let Some(notify) = &self.notify else {
+            return;
+        };
+        let result = serde_json::to_string(event)
+            .map_err(anyhow::Error::from)
+            .and_then(|body| self.queue.push(&self.urls, &body));
+        match result {
+            Ok(()) => {
+                let _ = notify.send(());
+            }
+            Err(e) => warn!("Could not queue webhook: {e:#}"),
+        }
+    }
+}
+
+impl Drop for Webhooks {
+    /// Waits a few seconds for the queued webhooks to be delivered, so that
+    /// events of short-lived commands are not left behind without holding up
+    /// exiting while an endpoint is unreachable. Undelivered webhooks stay
+    /// queued.
+    fn drop(&mut self) {
+        drop(self.notify.take());
+        let Some(worker) = self.worker.take() else {
+            return;
+        };
+        let start = Instant::now();
+        while !worker.is_finished() {
+            if start.elapsed() >= FLUSH_TIMEOUT {
+                warn!("Not waiting any longer for webhooks to be delivered, keeping them queued");
+                return;
+            }
+            sleep(FLUSH_POLL_INTERVAL);
+        }
+        if worker.join().is_err() {
+            warn!("The webhooks worker panicked");
+        }
+    }
+}
+
+impl Queue {
+    /// Appends a webhook for each URL to the queue, dropping the oldest ones
+    /// exceeding the maximum queue length.
+    fn push(
+        &self,
+        urls: &[String],
+        body: &str,
+    ) -> Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(dir) = self.file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file)?;
+        let id = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        for (index, url) in urls.iter().enumerate() {
+            let webhook = QueuedWebhook {
+                id: id + index as u128,
+                url: url.clone(),
+                body: body.to_string(),
+            };
+            let mut line = serde_json::to_string(&webhook)?;
+            line.push('\n');
+            file.write_all(line.as_bytes())?;
+        }
+        let webhooks = self.read()?;
+        if webhooks.len() > self.max_queued {
+            self.write(webhooks)?;
+        }
+        Ok(())
+    }
+
+    /// Delivers the queued webhooks in order, removing each from the queue
+    /// once delivered. Once a webhook for a URL cannot be delivered, the
+    /// following ones for that URL are kept for later so that each URL
+    /// receives the events in order.
+    fn deliver(
+        &self,
+        delivery: Delivery,
+    ) {
+        let webhooks = match self.load() {
+            Ok(webhooks) => webhooks,
+            Err(e) => {
+                warn!("Could not read the webhook queue: {e:#}");
+                return;
+            }
+        };
+        let mut failed_urls = BTreeSet::new();
+        for webhook in &webhooks {
+            if failed_urls.contains(&webhook.url) {
+                continue;
+            }
+            match post_with_retries(&webhook.url, &webhook.body, delivery) {
+                // Removed right away so that it is not delivered again if the
+                // process exits before the rest of the queue is delivered
+                Ok(()) => {
+                    if let Err(e) = self.remove(&BTreeSet::from([webhook.id])) {
+                        warn!("Could not update the webhook queue: {e:#}");
+                    }
+                }
+                Err(e) => {
+                    warn!(
+                        "Could not deliver webhook to {}, keeping it queued: {e:#}",
+                        webhook.url
+                    );
+                    failed_urls.insert(webhook.url.clone());
+                }
+            }
+        }
+    }
+
+    fn load(&self) -> Result<Vec<QueuedWebhook>> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.read()
+    }
+
+    /// Reads the queue, the caller must hold the lock.
+    fn read(&self) -> Result<Vec<QueuedWebhook>> {
+        let raw_queue = match fs::read_to_string(&self.file) {
+            Ok(raw_queue) => raw_queue,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(raw_queue
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(webhook) => Some(webhook),
+                Err(e) => {
+                    warn!("Dropping unreadable webhook from the queue: {e}");
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// Removes the delivered webhooks. Webhooks queued in the meantime are
+    /// kept.
+    fn remove(
+        &self,
+        delivered: &BTreeSet<u128>,
+    ) -> Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut webhooks = self.read()?;
+        webhooks.retain(|webhook| !delivered.contains(&webhook.id));
+        self.write(webhooks)
+    }
+
+    /// Replaces the queue, dropping the oldest webhooks exceeding the maximum
+    /// queue length. The caller must hold the lock.
+    fn write(
+        &self,
+        mut webhooks: Vec<QueuedWebhook>,
+    ) -> Result<()> {
+        if webhooks.len() > self.max_queued {
+            let dropped = webhooks.len() - self.max_queued;
+            warn!("Dropping the {dropped} oldest undelivered webhooks");
+            webhooks.drain(..dropped);
+        }
+        let mut raw_queue = String::new();
+        for webhook in &webhooks {
+            raw_queue.push_str(&serde_json::to_string(webhook)?);
+            raw_queue.push('\n');
+        }
+        write_atomically(&self.file, raw_queue.as_bytes())
+    }
+}
+
+fn post_with_retries(
+    url: &str,
+    body: &str,
+    delivery: Delivery,
+) -> Result<()> {
+    let mut retry_delay = delivery.retry_delay;
+    let mut attempt = 1;
+    loop {
+        match post(url, body, delivery.timeout) {
+            Ok(()) => {
+                debug!("Delivered webhook to {url}");
+                return Ok(());
+            }
+            Err(e) if attempt < delivery.max_attempts => {
+                debug!("Attempt {attempt} to deliver webhook to {url} failed: {e:#}");
+                sleep(retry_delay);
+                retry_delay = retry_delay.saturating_mul(2).min(MAX_RETRY_DELAY);
+                attempt += 1;
+            }
+            Err(e) => return Err(e.context(format!("giving up after {attempt} attempts"))),
+        }
+    }
+}
+
+/// Posts a JSON body to a plain HTTP URL, succeeding on any 2xx status.
+fn post(
+    url: &str,
+    body: &str,
+    timeout: Duration,
+) -> Result<()> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| anyhow!("only http:// URLs are supported"))?;
+    let (host, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{host}:80")
+    };
+    let socket_address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow!("could not resolve {host}"))?;
+    let mut stream = TcpStream::connect_timeout(&socket_address, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let request = format!(
+        "POST {path} HTTP/1.1\r\nHost: {host}\r\nUser-Agent: standup\r\nContent-Type: \
+         application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(request.as_bytes())?;
+    let mut status_line = String::new();
+    BufReader::new(&stream).read_line(&mut status_line)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .context("malformed HTTP response")?;
+    if status.starts_with('2') {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "the server responded with {}",
+            status_line.trim_end()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::process;
+
+    use super::*;
+
+    /// Accepts `responses.len()` requests, answering them with the given
+    /// status codes, and returns the request bodies.
+    fn serve(
+        listener: TcpListener,
+        responses: &'static [u16],
+    ) -> JoinHandle<Vec<String>> {
+        thread::spawn(move || {
+            responses
+                .iter()
+                .map(|status| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut content_length = 0;
+                    loop {
+                        let mut header = String::new();
+                        reader.read_line(&mut header).unwrap();
+                        if header.trim_end().is_empty() {
+                            break;
+                        }
+                        if let Some(value) = header.strip_prefix("Content-Length: ") {
+                            content_length = value.trim_end().parse().unwrap();
+                        }
+                    }
+                    let mut body = vec![0; content_length];
+                    reader.read_exact(&mut body).unwrap();
+                    write!(
+                        stream,
+                        "HTTP/1.1 {status} Status\r\nContent-Length: 0\r\n\r\n"
+                    )
+                    .unwrap();
+                    String::from_utf8(body).unwrap()
+                })
+                .collect()
+        })
+    }
+
+    #[test]
+    fn delivers_with_retries_and_removes_delivered_webhooks() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = serve(listener, &[500, 200]);
+        let file = std::env::temp_dir().join(format!("standup-webhooks-{}.jsonl", process::id()));
+        let queue = Queue {
+            file: file.clone(),
+            lock: Mutex::new(()),
+            max_queued: MAX_QUEUED,
+        };
+        queue.push(&[url], r#"{"event":"test"}"#).unwrap();
+
+        queue.deliver(Delivery {
+            timeout: Duration::from_secs(5),
+            max_attempts: 3,
+            retry_delay: Duration::from_millis(1),
+        });
+
+        let bodies = server.join().unwrap();
+        assert_eq!(bodies, [r#"{"event":"test"}"#; 2]);
+        assert!(queue.load().unwrap().is_empty());
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn keeps_only_the_newest_webhooks_while_the_url_is_unreachable() {
+        // Nothing listens on the port once the listener is dropped
+        let urls = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            [format!("http://{}/hook", listener.local_addr().unwrap())]
+        };
+        let file = std::env::temp_dir().join(format!(
+            "standup-webhooks-unreachable-{}.jsonl",
+            process::id()
+        ));
+        let queue = Queue {
+            file: file.clone(),
+            lock: Mutex::new(()),
+            max_queued: 3,
+        };
+
+        for index in 0..5 {
+            queue
+                .push(&urls, &format!(r#"{{"index":{index}}}"#))
+                .unwrap();
+            queue.deliver(Delivery {
+                timeout: Duration::from_secs(1),
+                max_attempts: 1,
+                retry_delay: Duration::from_millis(1),
+            });
+        }
+
+        let bodies: Vec<_> = queue
+            .load()
+            .unwrap()
+            .into_iter()
+            .map(|webhook| webhook.body)
+            .collect();
+        assert_eq!(
+            bodies,
+            [r#"{"index":2}"#, r#"{"index":3}"#, r#"{"index":4}"#]
+        );
+        fs::remove_file(file).unwrap();
+    }
+}